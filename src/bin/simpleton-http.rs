@@ -4,7 +4,6 @@ use std::env;
 use std::io::BufReader;
 use std::io::prelude::*;
 use std::net::TcpStream;
-use std::str;
 
 use simpleton::http::Request;
 
@@ -38,7 +37,10 @@ fn main() {
         Ok(stream) => stream
     };
 
-    req.send(&stream);
+    if let Err(e) = req.send(&stream) {
+        println!("Error: {}", e);
+        return;
+    }
 
     if verbose {
         for line in req.to_string().lines() {
@@ -59,7 +61,7 @@ fn main() {
                 } else {
                     println!("{}", line);
                 }
-                if line == "" {
+                if line.is_empty() {
                     is_header = false;
                 }
             }
@@ -2,15 +2,29 @@ extern crate getopts;
 extern crate simpleton;
 
 use std::env;
+use std::fs;
+use std::time::Duration;
 
 use getopts::Options;
 use simpleton::http;
+use simpleton::http::log::{self, LogSink, Rotation};
 
 fn print_usage(program: &str, opts: Options) {
     let brief = format!("Usage: {} [options]", program);
     print!("{}", opts.usage(&brief));
 }
 
+/// Parse a size in bytes with an optional `K`, `M` or `G` suffix.
+fn parse_size(s: &str) -> Option<usize> {
+    let (digits, multiplier) = match s.chars().last() {
+        Some('K') | Some('k') => (&s[..s.len() - 1], 1 << 10),
+        Some('M') | Some('m') => (&s[..s.len() - 1], 1 << 20),
+        Some('G') | Some('g') => (&s[..s.len() - 1], 1 << 30),
+        _                     => (s, 1)
+    };
+    digits.parse::<usize>().ok().and_then(|n| n.checked_mul(multiplier))
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
     let program = args[0].clone();
@@ -18,6 +32,45 @@ fn main() {
     let mut opts = Options::new();
     opts.optopt("a", "address", "Bind to HOST address (default: 0.0.0.0)", "HOST");
     opts.optopt("p", "port", "Use PORT (default: 3000)", "HOST");
+    opts.optopt("l", "log-format", "Use FORMAT for the access log: common, combined, json or a template (default: common)", "FORMAT");
+    opts.optopt("", "access-log", "Write the access log to FILE (default: stdout)", "FILE");
+    opts.optopt("", "error-log", "Write the error log to FILE (default: stderr)", "FILE");
+    opts.optopt("", "log-rotate", "Rotate log files: never, hourly, daily or a SIZE like 10M (default: never)", "WHEN");
+    opts.optopt("", "log-keep", "Keep COUNT rotated log files (default: 7)", "COUNT");
+    opts.optflag("", "precompressed", "Serve precompressed .br and .gz siblings of static files");
+    opts.optmulti("", "cache", "Set Cache-Control for files matching PATTERN, like '/assets/**=max-age=86400' or 'text/html=no-cache'", "PATTERN=DIRECTIVES");
+    opts.optmulti("", "vhost", "Serve files from ROOT for the hosts matching NAME, like 'www.example.test=/var/www' or '*.example.test=/srv'", "NAME=ROOT");
+    opts.optopt("", "rewrite-rules", "Rewrite or redirect requests with the rules in FILE", "FILE");
+    opts.optmulti("", "trusted-proxy", "Use the client address, scheme and host forwarded by the proxies in CIDR, like '10.0.0.0/8'", "CIDR");
+    opts.optopt("", "acl", "Allow or deny clients by IP address with the rules in FILE", "FILE");
+    opts.optopt("", "htpasswd", "Require the credentials of the users in the htpasswd FILE", "FILE");
+    opts.optopt("", "htdigest", "Require the credentials of the users in the htdigest FILE with the Digest scheme", "FILE");
+    opts.optmulti("", "auth-prefix", "Require credentials only for paths starting with PREFIX, like '/private'", "PREFIX");
+    opts.optopt("", "auth-realm", "Name the protected area REALM (default: Restricted)", "REALM");
+    opts.optopt("", "try-files", "Resolve static files with CANDIDATES, like '$uri $uri.html $uri/ /index.html' or '$uri =404'", "CANDIDATES");
+    opts.optflag("", "deny-hidden", "Deny access to hidden files, except .well-known");
+    opts.optmulti("", "deny", "Deny access to files matching PATTERN, like '/**/*.bak'", "PATTERN");
+    opts.optopt("", "symlinks", "Follow symlinks: follow, within-root or deny (default: follow)", "POLICY");
+    opts.optopt("", "deny-status", "Respond to denied requests with STATUS: 403 or 404 (default: 404)", "STATUS");
+    opts.optopt("", "file-cache", "Cache up to SIZE bytes of static files in memory, like 64M", "SIZE");
+    opts.optopt("", "file-cache-max-entry", "Cache only files smaller than SIZE bytes (default: 1M)", "SIZE");
+    opts.optflag("", "compress", "Compress responses with gzip or deflate");
+    opts.optopt("", "metrics", "Serve metrics in Prometheus format at PATH", "PATH");
+    opts.optopt("", "proxy", "Forward requests to UPSTREAMS, like '127.0.0.1:8080,127.0.0.1:8081'", "UPSTREAMS");
+    opts.optopt("", "proxy-prefix", "Forward only requests with a path starting with PREFIX (default: /)", "PREFIX");
+    opts.optflag("", "proxy-preserve-host", "Forward the Host header of the client to the upstreams");
+    opts.optopt("", "forward-proxy", "Act as a forward proxy to the destinations matching PATTERNS, like '*.example.com:443,10.0.0.*'", "PATTERNS");
+    opts.optopt("", "forward-proxy-auth", "Require CREDENTIALS in the Proxy-Authorization header", "USER:PASSWORD");
+    opts.optopt("", "cgi-dir", "Execute CGI scripts from DIR", "DIR");
+    opts.optopt("", "cgi-prefix", "Execute scripts with a path starting with PREFIX (default: /cgi-bin/)", "PREFIX");
+    opts.optmulti("", "cgi-ext", "Execute scripts with the extension EXT, like 'cgi' or 'pl'", "EXT");
+    opts.optopt("", "cgi-timeout", "Stop scripts running for more than SECONDS (default: 30)", "SECONDS");
+    opts.optopt("", "fastcgi", "Forward requests for scripts to the FastCGI application at ADDRESS, like '127.0.0.1:9000' or 'unix:/run/php-fpm.sock'", "ADDRESS");
+    opts.optmulti("", "fastcgi-ext", "Forward requests for scripts with the extension EXT (default: php)", "EXT");
+    opts.optopt("", "fastcgi-prefix", "Forward all requests with a path starting with PREFIX", "PREFIX");
+    opts.optopt("", "fastcgi-root", "Use ROOT as the document root of the application", "ROOT");
+    opts.optflag("", "fastcgi-multiplex", "Multiplex requests over a single connection");
+    opts.optflag("", "server-timing", "Add a Server-Timing header to responses");
     opts.optflag("h", "help", "Show this message");
     let matches = match opts.parse(&args[1..]) {
         Ok(m)  => { m }
@@ -31,13 +84,217 @@ fn main() {
     let address = matches.opt_str("a").unwrap_or("0.0.0.0".into());
     let port = matches.opt_str("p").unwrap_or("3000".into());
 
-    let binding = vec![address, port].join(":");
+    let binding = [address, port].join(":");
 
     let mut server = http::Server::new();
 
+    if let Some(log_format) = matches.opt_str("l") {
+        server.log_format = http::handlers::print_log::LogFormat::from_name(&log_format);
+    }
+
+    server.server_timing = matches.opt_present("server-timing");
+    server.serve_precompressed = matches.opt_present("precompressed");
+
+    for rule in matches.opt_strs("cache") {
+        let mut fields = rule.splitn(2, '=');
+        match (fields.next(), fields.next()) {
+            (Some(pattern), Some(directives)) => server.add_cache_rule(pattern, directives),
+            _                                 => return print_usage(&program, opts)
+        }
+    }
+
+    for cidr in matches.opt_strs("trusted-proxy") {
+        match http::cidr::Cidr::parse(&cidr) {
+            Ok(cidr) => server.trusted_proxies.push(cidr),
+            Err(e)   => { println!("Error: {}", e); return }
+        }
+    }
+
+    if let Some(path) = matches.opt_str("acl") {
+        let acls = fs::read_to_string(&path).map_err(|e| e.to_string()).and_then(|text| {
+            http::handlers::acl::parse_acls(&text)
+        });
+        match acls {
+            Ok(acls) => server.acls = acls,
+            Err(e)   => { println!("Error: {}: {}", path, e); return }
+        }
+        server.add_handler(http::handlers::acl::handler);
+    }
+
+    if let Some(patterns) = matches.opt_str("forward-proxy") {
+        let patterns = patterns.split(',').map(|pattern| pattern.trim().into()).collect();
+        let mut forward_proxy = http::handlers::forward_proxy::ForwardProxy::new(patterns);
+        forward_proxy.credentials = matches.opt_str("forward-proxy-auth");
+        server.forward_proxy = Some(forward_proxy);
+        server.add_handler(http::handlers::forward_proxy::handler);
+    }
+
+    if let Some(path) = matches.opt_str("rewrite-rules") {
+        let rules = fs::read_to_string(&path).map_err(|e| e.to_string()).and_then(|text| {
+            http::handlers::rewrite::parse_rules(&text)
+        });
+        match rules {
+            Ok(rules) => server.rewrite_rules = rules,
+            Err(e)    => { println!("Error: {}: {}", path, e); return }
+        }
+        server.add_handler(http::handlers::rewrite::handler);
+
+        // The ACLs also apply to the path of the rewritten request
+        if !server.acls.is_empty() {
+            server.add_handler(http::handlers::acl::handler);
+        }
+    }
+
+    if let Some(path) = matches.opt_str("htpasswd") {
+        let users = match http::handlers::basic_auth::Htpasswd::from_file(&path) {
+            Ok(users) => users,
+            Err(e)    => { println!("Error: {}", e); return }
+        };
+        let mut auth = http::handlers::basic_auth::BasicAuth::new(users);
+        if matches.opt_present("auth-prefix") {
+            auth.path_prefixes = matches.opt_strs("auth-prefix");
+        }
+        if let Some(realm) = matches.opt_str("auth-realm") {
+            auth.realm = realm;
+        }
+        server.basic_auth = Some(auth);
+        server.add_handler(http::handlers::basic_auth::handler);
+    }
+
+    if let Some(path) = matches.opt_str("htdigest") {
+        let users = match http::handlers::digest_auth::Htdigest::from_file(&path) {
+            Ok(users) => users,
+            Err(e)    => { println!("Error: {}", e); return }
+        };
+        let realm = matches.opt_str("auth-realm").unwrap_or("Restricted".into());
+        let mut auth = http::handlers::digest_auth::DigestAuth::new(&realm, users);
+        if matches.opt_present("auth-prefix") {
+            auth.path_prefixes = matches.opt_strs("auth-prefix");
+        }
+        server.digest_auth = Some(auth);
+        server.add_handler(http::handlers::digest_auth::handler);
+    }
+
+    if let Some(candidates) = matches.opt_str("try-files") {
+        server.try_files = candidates.split_whitespace().map(|candidate| candidate.into()).collect();
+    }
+
+    server.deny_hidden = matches.opt_present("deny-hidden");
+    server.deny_patterns = matches.opt_strs("deny");
+    if let Some(policy) = matches.opt_str("symlinks") {
+        server.symlinks = match http::handlers::serve_static::Symlinks::parse(&policy) {
+            Ok(symlinks) => symlinks,
+            Err(e)       => { println!("Error: {}", e); return }
+        };
+    }
+    match matches.opt_str("deny-status").as_deref() {
+        None        => {},
+        Some("403") => server.deny_status = 403,
+        Some("404") => server.deny_status = 404,
+        Some(_)     => return print_usage(&program, opts)
+    }
+
+    if let Some(size) = matches.opt_str("file-cache") {
+        let max_size = parse_size(&size);
+        let max_entry_size = parse_size(&matches.opt_str("file-cache-max-entry").unwrap_or("1M".into()));
+        match (max_size, max_entry_size) {
+            (Some(max_size), Some(max_entry_size)) => {
+                server.file_cache = Some(http::file_cache::FileCache::new(max_size, max_entry_size));
+            },
+            _ => return print_usage(&program, opts)
+        }
+    }
+
+    if matches.opt_present("compress") {
+        server.compression = Some(http::compression::Compression::new());
+    }
+
+    let rotation = match Rotation::parse(&matches.opt_str("log-rotate").unwrap_or("never".into())) {
+        Ok(rotation) => rotation,
+        Err(e)       => { println!("Error: {}", e); return }
+    };
+    let retention = match matches.opt_str("log-keep").unwrap_or("7".into()).parse() {
+        Ok(retention) => retention,
+        Err(_)        => return print_usage(&program, opts)
+    };
+    if let Some(path) = matches.opt_str("access-log") {
+        match LogSink::file(&path, rotation, retention) {
+            Ok(sink) => server.access_log = sink,
+            Err(e)   => { println!("Error: {}", e); return }
+        }
+    }
+    if let Some(path) = matches.opt_str("error-log") {
+        match LogSink::file(&path, rotation, retention) {
+            Ok(sink) => server.error_log = sink,
+            Err(e)   => { println!("Error: {}", e); return }
+        }
+    }
+    if matches.opt_present("access-log") || matches.opt_present("error-log") {
+        log::reopen_on_sighup();
+    }
+
+    if let Some(path) = matches.opt_str("metrics") {
+        server.metrics = Some(http::handlers::metrics::Metrics::new());
+        server.metrics_path = path;
+        server.add_handler(http::handlers::metrics::handler);
+    }
+
+    if let Some(upstreams) = matches.opt_str("proxy") {
+        let upstreams = upstreams.split(',').map(|upstream| upstream.trim().into()).collect();
+        let mut proxy = http::handlers::proxy::Proxy::new(upstreams);
+        if let Some(prefix) = matches.opt_str("proxy-prefix") {
+            proxy.path_prefix = prefix;
+        }
+        proxy.preserve_host = matches.opt_present("proxy-preserve-host");
+        server.proxy = Some(proxy);
+        server.add_handler(http::handlers::proxy::handler);
+    }
+
+    if let Some(dir) = matches.opt_str("cgi-dir") {
+        let mut cgi = http::handlers::cgi::Cgi::new(&dir);
+        if let Some(prefix) = matches.opt_str("cgi-prefix") {
+            cgi.path_prefix = prefix;
+        }
+        cgi.extensions = matches.opt_strs("cgi-ext");
+        if let Some(timeout) = matches.opt_str("cgi-timeout") {
+            match timeout.parse() {
+                Ok(seconds) => cgi.timeout = Duration::from_secs(seconds),
+                Err(_)      => return print_usage(&program, opts)
+            }
+        }
+        server.cgi = Some(cgi);
+        server.add_handler(http::handlers::cgi::handler);
+    }
+
+    if let Some(address) = matches.opt_str("fastcgi") {
+        let mut fastcgi = http::handlers::fastcgi::FastCgi::new(&address);
+        if matches.opt_present("fastcgi-ext") {
+            fastcgi.extensions = matches.opt_strs("fastcgi-ext");
+        }
+        fastcgi.path_prefix = matches.opt_str("fastcgi-prefix");
+        fastcgi.root_path = matches.opt_str("fastcgi-root");
+        fastcgi.multiplex = matches.opt_present("fastcgi-multiplex");
+        server.fastcgi = Some(fastcgi);
+        server.add_handler(http::handlers::fastcgi::handler);
+    }
+
     server.add_handler(http::handlers::serve_static::handler);
     server.add_handler(http::handlers::print_log::handler);
 
+    // Virtual hosts share the configuration of the default host
+    for vhost in matches.opt_strs("vhost") {
+        let mut fields = vhost.splitn(2, '=');
+        match (fields.next(), fields.next()) {
+            (Some(name), Some(root)) => {
+                let mut host = server.clone();
+                host.virtual_hosts = Default::default();
+                host.root_path = root.into();
+                server.add_virtual_host(name, host);
+            },
+            _ => return print_usage(&program, opts)
+        }
+    }
+
     println!("{}", server.name);
     println!("Listening on {}", binding);
 
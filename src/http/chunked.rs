@@ -0,0 +1,110 @@
+use std::cmp;
+use std::io;
+use std::io::prelude::*;
+
+/// Reader decoding a message-body sent with the chunked transfer coding.
+///
+/// (RFC 7230 4.1)
+pub struct ChunkedReader<R> {
+    reader: R,
+
+    /// Number of bytes of the current chunk not read yet.
+    remaining: u64,
+
+    /// Boolean indicating if the last chunk has been read.
+    done: bool
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    pub fn new(reader: R) -> ChunkedReader<R> {
+        ChunkedReader {
+            reader,
+            remaining: 0,
+            done: false
+        }
+    }
+
+    /// Read the size of the next chunk, and the trailer section after
+    /// the last chunk.
+    fn read_chunk_size(&mut self) -> io::Result<()> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        // chunk = chunk-size [ chunk-ext ] CRLF chunk-data CRLF
+        let size = line.split(';').next().unwrap().trim();
+        self.remaining = match u64::from_str_radix(size, 16) {
+            Ok(size) => size,
+            Err(_)   => return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid chunk size"))
+        };
+
+        if self.remaining == 0 {
+            // Skip the trailer section
+            loop {
+                line.clear();
+                if self.reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                    break;
+                }
+            }
+            self.done = true;
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 && !self.done {
+            self.read_chunk_size()?;
+        }
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let len = cmp::min(buf.len() as u64, self.remaining) as usize;
+        let n = self.reader.read(&mut buf[..len])?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= n as u64;
+
+        // Skip the CRLF at the end of the chunk
+        if self.remaining == 0 {
+            let mut line = String::new();
+            self.reader.read_line(&mut line)?;
+        }
+
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let message = "7\r\nHello, \r\n6;ext=1\r\nWorld!\r\n0\r\nExpires: never\r\n\r\nNext";
+        let mut reader = io::BufReader::new(message.as_bytes());
+
+        let mut body = String::new();
+        ChunkedReader::new(&mut reader).read_to_string(&mut body).unwrap();
+        assert_eq!(body, "Hello, World!");
+
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "Next");
+    }
+
+    #[test]
+    fn test_read_invalid() {
+        let mut body = vec![];
+        let mut reader = ChunkedReader::new("z\r\nHello\r\n0\r\n\r\n".as_bytes());
+        assert!(reader.read_to_end(&mut body).is_err());
+
+        let mut reader = ChunkedReader::new("10\r\nHello\r\n".as_bytes());
+        assert!(reader.read_to_end(&mut body).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+use std::fmt;
+use std::net::IpAddr;
+
+/// Block of IPv4 or IPv6 addresses sharing a prefix, like `10.0.0.0/8`
+/// or `2001:db8::/32`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8
+}
+
+impl Cidr {
+    /// Parse a block in CIDR notation, or a single address.
+    pub fn parse(s: &str) -> Result<Cidr, String> {
+        let invalid = || format!("Could not parse CIDR block '{}'", s);
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None                     => (s, None)
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len.parse().map_err(|_| invalid())?,
+            None             => max_len
+        };
+        if prefix_len > max_len {
+            return Err(invalid());
+        }
+
+        // IPv4-mapped blocks are converted like the addresses they contain
+        match normalize(addr) {
+            IpAddr::V4(v4) if addr.is_ipv6() && prefix_len >= 96 => {
+                Ok(Cidr { addr: IpAddr::V4(v4), prefix_len: prefix_len - 96 })
+            },
+            _ => Ok(Cidr { addr, prefix_len })
+        }
+    }
+
+    /// Return `true` if `addr` is in the block.
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match (self.addr, normalize(*addr)) {
+            (IpAddr::V4(block), IpAddr::V4(addr)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_len as u32).unwrap_or(0);
+                u32::from(block) & mask == u32::from(addr) & mask
+            },
+            (IpAddr::V6(block), IpAddr::V6(addr)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_len as u32).unwrap_or(0);
+                u128::from(block) & mask == u128::from(addr) & mask
+            },
+            _ => false
+        }
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+/// Convert an IPv4-mapped IPv6 address, like `::ffff:192.0.2.1` for a client
+/// connected to a dual-stack socket, to its IPv4 address.
+pub fn normalize(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+        IpAddr::V4(_)  => addr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Cidr::parse("10.0.0.0/8").unwrap().to_string(), "10.0.0.0/8");
+        assert_eq!(Cidr::parse("192.0.2.1").unwrap().to_string(), "192.0.2.1/32");
+        assert_eq!(Cidr::parse("2001:db8::/32").unwrap().to_string(), "2001:db8::/32");
+        assert_eq!(Cidr::parse("::ffff:192.0.2.0/120").unwrap().to_string(), "192.0.2.0/24");
+        assert!(Cidr::parse("10.0.0.0/33").is_err());
+        assert!(Cidr::parse("10.0.0/8").is_err());
+        assert!(Cidr::parse("all").is_err());
+    }
+
+    #[test]
+    fn test_contains() {
+        let cidr = Cidr::parse("10.1.0.0/16").unwrap();
+        assert!(cidr.contains(&"10.1.2.3".parse().unwrap()));
+        assert!(cidr.contains(&"::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!cidr.contains(&"10.2.0.1".parse().unwrap()));
+        assert!(!cidr.contains(&"::1".parse().unwrap()));
+
+        let cidr = Cidr::parse("2001:db8::/32").unwrap();
+        assert!(cidr.contains(&"2001:db8:1::1".parse().unwrap()));
+        assert!(!cidr.contains(&"2001:db9::1".parse().unwrap()));
+
+        assert!(Cidr::parse("0.0.0.0/0").unwrap().contains(&"192.0.2.1".parse().unwrap()));
+        assert!(Cidr::parse("::/0").unwrap().contains(&"::1".parse().unwrap()));
+    }
+}
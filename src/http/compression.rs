@@ -0,0 +1,275 @@
+use std::io::prelude::*;
+
+use flate2;
+use flate2::read::{GzEncoder, ZlibEncoder};
+use flate2::write;
+
+use http::headers;
+use http::request::Request;
+use http::response::Response;
+
+/// Content coding of a compressed response
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Gzip,
+    Deflate
+}
+
+impl Encoding {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Encoding::Gzip    => "gzip",
+            Encoding::Deflate => "deflate"
+        }
+    }
+}
+
+/// Configuration of the compression of response bodies
+#[derive(Clone, Debug)]
+pub struct Compression {
+    /// Minimum length in bytes of a body to be compressed.
+    pub min_length: usize,
+
+    /// Content types of the bodies to compress.
+    pub content_types: Vec<String>,
+
+    /// Level of compression, from `0` (none) to `9` (best).
+    pub level: u32
+}
+
+impl Compression {
+    pub fn new() -> Compression {
+        let content_types = vec![
+            "text/html", "text/plain", "text/css", "text/csv", "text/xml",
+            "text/javascript", "application/javascript", "application/json",
+            "application/problem+json", "application/xml", "image/svg+xml"
+        ];
+
+        Compression {
+            min_length: 1024,
+            content_types: content_types.iter().map(|&t| t.into()).collect(),
+            level: 6
+        }
+    }
+
+    /// Return `true` if bodies of `content_type` should be compressed.
+    pub fn is_compressible(&self, content_type: &str) -> bool {
+        let media_type = content_type.split(';').next().unwrap().trim().to_lowercase();
+        self.content_types.contains(&media_type)
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression::new()
+    }
+}
+
+/// Choose the content coding preferred by the client in the value
+/// of an `Accept-Encoding` header, preferring `gzip` on equal terms.
+pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let codings = headers::quality_values(accept_encoding);
+
+    let quality = |names: &[&str]| {
+        codings.iter().find(|&(coding, _)| names.contains(&coding.as_str()))
+            .or_else(|| codings.iter().find(|&(coding, _)| coding == "*"))
+            .map_or(0.0, |&(_, q)| q)
+    };
+    let gzip = quality(&["gzip", "x-gzip"]);
+    let deflate = quality(&["deflate"]);
+
+    if gzip > 0.0 && gzip >= deflate {
+        Some(Encoding::Gzip)
+    } else if deflate > 0.0 {
+        Some(Encoding::Deflate)
+    } else {
+        None
+    }
+}
+
+/// Compress the body of the response, buffered or streamed, with the
+/// content coding accepted by the client, when it is enabled in the server
+/// and the body is compressible.
+pub fn compress(req: &Request, mut res: Response) -> Response {
+    let config = match res.server.compression {
+        None             => return res,
+        Some(ref config) => config.clone()
+    };
+    if res.is_sent() || req.method == "HEAD" {
+        return res;
+    }
+    if res.status_code < 200 || res.status_code == 204 || res.status_code == 304 {
+        return res;
+    }
+    if res.headers.contains_key("content-encoding") {
+        return res;
+    }
+    match res.headers.get("content-type") {
+        Some(content_type) if config.is_compressible(content_type) => {},
+        _ => return res
+    }
+    // A streamed body of unknown length is always compressed
+    let length = match res.headers.get("content-length") {
+        Some(length)              => length.parse().unwrap_or(0),
+        None if res.is_streamed() => usize::MAX,
+        None                      => res.body().len()
+    };
+    if length < config.min_length {
+        return res;
+    }
+
+    // The representation depends on the content codings accepted
+    // by the client.
+    let vary = match res.headers.get("vary") {
+        Some(vary) if !vary.to_lowercase().contains("accept-encoding") => format!("{}, Accept-Encoding", vary),
+        Some(vary) => vary.clone(),
+        None       => "Accept-Encoding".into()
+    };
+    res.headers.set("vary", &vary);
+
+    let encoding = match req.headers.get("accept-encoding").and_then(|value| negotiate(value)) {
+        None           => return res,
+        Some(encoding) => encoding
+    };
+    let level = flate2::Compression::new(config.level);
+
+    if let Some(reader) = res.take_reader() {
+        // The length of the compressed body is unknown until it has been
+        // streamed.
+        res.headers.remove("content-length");
+        match encoding {
+            Encoding::Gzip    => res.send_reader(GzEncoder::new(reader, level)),
+            Encoding::Deflate => res.send_reader(ZlibEncoder::new(reader, level))
+        }
+    } else {
+        let body = match encoding {
+            Encoding::Gzip => {
+                let mut encoder = write::GzEncoder::new(vec![], level);
+                encoder.write_all(res.body()).and_then(|_| encoder.finish())
+            },
+            Encoding::Deflate => {
+                let mut encoder = write::ZlibEncoder::new(vec![], level);
+                encoder.write_all(res.body()).and_then(|_| encoder.finish())
+            }
+        };
+        match body {
+            Ok(body) => {
+                res.headers.set("content-length", &body.len().to_string());
+                res.set_body(body);
+            },
+            Err(_) => return res
+        }
+    }
+    res.headers.set("content-encoding", encoding.name());
+
+    // The compressed representation is not byte-for-byte identical to the
+    // uncompressed one, its entity-tag can only be weak.
+    //
+    // (RFC 7232 2.1)
+    if let Some(etag) = res.headers.get("etag").cloned() {
+        if !etag.starts_with("W/") {
+            res.headers.set("etag", &format!("W/{}", etag));
+        }
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use flate2::read::GzDecoder;
+    use http::server::Server;
+
+    fn response(body: &[u8]) -> Response {
+        let mut server = Server::new();
+        server.compression = Some(Compression::new());
+
+        let mut res = Response::new(server);
+        res.headers.set("content-type", "text/html; charset=utf-8");
+        res.send(body);
+
+        res
+    }
+
+    fn request(accept_encoding: &str) -> Request {
+        let mut req = Request::new("GET", "example.com", "/");
+        req.headers.set("accept-encoding", accept_encoding);
+
+        req
+    }
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate(""), None);
+        assert_eq!(negotiate("gzip, deflate"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("gzip;q=0.5, deflate"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("*"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("gzip;q=0, *"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("br, identity"), None);
+    }
+
+    #[test]
+    fn test_compress() {
+        let body = "Hello, World!\n".repeat(100);
+        let res = compress(&request("gzip"), response(body.as_bytes()));
+        assert_eq!(res.headers.get("content-encoding"), Some(&"gzip".into()));
+        assert_eq!(res.headers.get("vary"), Some(&"Accept-Encoding".into()));
+        assert!(res.body().len() < body.len());
+
+        let mut decoded = String::new();
+        GzDecoder::new(res.body()).read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, body);
+    }
+
+    #[test]
+    fn test_compress_etag() {
+        let body = "Hello, World!\n".repeat(100);
+        let mut res = response(body.as_bytes());
+        res.headers.set("etag", "\"1f4-5f2b1c3a\"");
+        let res = compress(&request("gzip"), res);
+        assert_eq!(res.headers.get("etag"), Some(&"W/\"1f4-5f2b1c3a\"".into()));
+
+        // The entity-tag of an uncompressed response stays strong
+        let mut res = response(body.as_bytes());
+        res.headers.set("etag", "\"1f4-5f2b1c3a\"");
+        let res = compress(&request("identity"), res);
+        assert_eq!(res.headers.get("etag"), Some(&"\"1f4-5f2b1c3a\"".into()));
+    }
+
+    #[test]
+    fn test_compress_streamed() {
+        let body = "Hello, World!\n".repeat(100);
+        let mut res = response(b"");
+        res.send_reader(Cursor::new(body.clone().into_bytes()));
+
+        let mut res = compress(&request("gzip"), res);
+        assert_eq!(res.headers.get("content-encoding"), Some(&"gzip".into()));
+
+        let mut decoded = String::new();
+        GzDecoder::new(res.take_reader().unwrap()).read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, body);
+    }
+
+    #[test]
+    fn test_compress_skipped() {
+        // Body too small
+        let res = compress(&request("gzip"), response(b"Hello, World!"));
+        assert!(!res.headers.contains_key("content-encoding"));
+
+        // Encoding not accepted
+        let body = "Hello, World!\n".repeat(100);
+        let res = compress(&request("identity"), response(body.as_bytes()));
+        assert!(!res.headers.contains_key("content-encoding"));
+        assert_eq!(res.headers.get("vary"), Some(&"Accept-Encoding".into()));
+
+        // Content type not compressible
+        let mut res = response(body.as_bytes());
+        res.headers.set("content-type", "image/png");
+        let res = compress(&request("gzip"), res);
+        assert!(!res.headers.contains_key("content-encoding"));
+    }
+}
@@ -0,0 +1,46 @@
+/// Escape `s` to be used as text or as an attribute value in an HTML
+/// document.
+pub fn html(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _   => escaped.push(c)
+        }
+    }
+    escaped
+}
+
+/// Escape `s` to be used inside a JSON string.
+pub fn json(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '"'  => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                escaped.push_str(&format!("\\u{:04x}", c as u32))
+            },
+            _    => escaped.push(c)
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_html() {
+        assert_eq!(html("<a href=\"/\">R&D</a>"), "&lt;a href=&quot;/&quot;&gt;R&amp;D&lt;/a&gt;");
+    }
+
+    #[test]
+    fn test_json() {
+        assert_eq!(json("say \"hi\"\\\n"), "say \\\"hi\\\"\\\\\\u000a");
+    }
+}
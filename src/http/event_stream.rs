@@ -0,0 +1,206 @@
+use std::cmp;
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+use http::request::Request;
+use http::response::Response;
+
+/// Event sent to a client of an event stream
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    /// Identifier of the event, given back by the client in its
+    /// `Last-Event-ID` header when it reconnects.
+    pub id: Option<String>,
+
+    /// Type of the event, `message` when it is `None`.
+    pub event: Option<String>,
+
+    /// Data of the event, sent in a `data` field for each of its lines.
+    pub data: String,
+
+    /// Reconnection time in milliseconds the client should use if the
+    /// connection is lost.
+    pub retry: Option<u64>
+}
+
+impl Event {
+    pub fn new(data: &str) -> Event {
+        Event {
+            id: None,
+            event: None,
+            data: data.into(),
+            retry: None
+        }
+    }
+}
+
+/// Remove the line breaks that would end a field.
+fn field_value(value: &str) -> String {
+    value.chars().filter(|&c| c != '\r' && c != '\n' && c != '\0').collect()
+}
+
+impl fmt::Display for Event {
+    /// Format the event in the `text/event-stream` format.
+    ///
+    /// (HTML Living Standard 9.2.5)
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref id) = self.id {
+            writeln!(f, "id: {}", field_value(id))?;
+        }
+        if let Some(ref event) = self.event {
+            writeln!(f, "event: {}", field_value(event))?;
+        }
+        if let Some(retry) = self.retry {
+            writeln!(f, "retry: {}", retry)?;
+        }
+        for line in self.data.split("\r\n").flat_map(|line| line.split(['\r', '\n'])) {
+            writeln!(f, "data: {}", line)?;
+        }
+
+        writeln!(f)
+    }
+}
+
+/// Sender of the events of a stream, which can be cloned and moved to
+/// other threads. The stream ends when all the senders are dropped.
+#[derive(Clone)]
+pub struct EventSender {
+    sender: Sender<String>
+}
+
+impl EventSender {
+    /// Send `event` to the client, or return an error if the client has
+    /// disconnected.
+    pub fn send(&self, event: &Event) -> io::Result<()> {
+        self.send_raw(event.to_string())
+    }
+
+    /// Send a comment ignored by the client.
+    pub fn comment(&self, text: &str) -> io::Result<()> {
+        self.send_raw(format!(": {}\n\n", field_value(text)))
+    }
+
+    fn send_raw(&self, text: String) -> io::Result<()> {
+        self.sender.send(text).map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Client disconnected"))
+    }
+}
+
+/// Reader of the events of a stream, streamed in the message-body
+/// of a response.
+struct EventReader {
+    receiver: Receiver<String>,
+
+    /// Interval after which a comment is sent to keep the connection alive
+    /// when there is no event to send.
+    keep_alive: Duration,
+
+    /// Part of the last event not read yet.
+    pending: Vec<u8>
+}
+
+impl Read for EventReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            self.pending = match self.receiver.recv_timeout(self.keep_alive) {
+                Ok(text)                            => text.into_bytes(),
+                Err(RecvTimeoutError::Timeout)      => b":\n\n".to_vec(),
+                Err(RecvTimeoutError::Disconnected) => return Ok(0)
+            };
+        }
+
+        let n = cmp::min(buf.len(), self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+
+        Ok(n)
+    }
+}
+
+/// Return the identifier of the last event received by a client
+/// reconnecting to an event stream.
+pub fn last_event_id(req: &Request) -> Option<&str> {
+    req.headers.get("last-event-id").map(|id| id.as_str())
+}
+
+/// Start an event stream in the response and end it, returning the sender
+/// of the events. A comment is sent when no event has been sent during
+/// `keep_alive`, to keep the connection alive and detect disconnected
+/// clients.
+///
+/// The events are written to the connection as they are sent, until all
+/// the senders are dropped or the client disconnects.
+pub fn start(res: &mut Response, keep_alive: Duration) -> EventSender {
+    let (sender, receiver) = mpsc::channel();
+    let reader = EventReader {
+        receiver,
+        keep_alive,
+        pending: Vec::new()
+    };
+
+    res.set_status(200);
+    res.headers.set("content-type", "text/event-stream");
+    res.headers.set("cache-control", "no-cache");
+    res.send_reader(reader);
+
+    EventSender { sender }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    use http::server::Server;
+
+    #[test]
+    fn test_event_to_string() {
+        let mut event = Event::new("Build started\nStep 1/3");
+        assert_eq!(event.to_string(), "data: Build started\ndata: Step 1/3\n\n");
+
+        event.id = Some("42".into());
+        event.event = Some("build\nstatus".into());
+        event.retry = Some(5000);
+        event.data = "".into();
+        assert_eq!(event.to_string(), "id: 42\nevent: buildstatus\nretry: 5000\ndata: \n\n");
+    }
+
+    #[test]
+    fn test_start() {
+        let mut req = Request::new("GET", "example.com", "/events");
+        assert_eq!(last_event_id(&req), None);
+        req.headers.set("last-event-id", "41");
+        assert_eq!(last_event_id(&req), Some("41"));
+
+        let mut res = Response::new(Server::new());
+        let events = start(&mut res, Duration::from_millis(100));
+        assert!(res.is_ended());
+        assert_eq!(res.headers.get("content-type"), Some(&"text/event-stream".into()));
+
+        let mut reader = res.take_reader().unwrap();
+        thread::spawn(move || {
+            let mut event = Event::new("Done");
+            event.id = Some("42".into());
+            events.comment("Hello").unwrap();
+            thread::sleep(Duration::from_millis(150));
+            events.send(&event).unwrap();
+        });
+
+        let mut body = String::new();
+        reader.read_to_string(&mut body).unwrap();
+        assert_eq!(body, ": Hello\n\n:\n\nid: 42\ndata: Done\n\n");
+    }
+
+    #[test]
+    fn test_send_disconnected() {
+        let mut res = Response::new(Server::new());
+        let events = start(&mut res, Duration::from_secs(15));
+        assert!(events.send(&Event::new("Hello")).is_ok());
+
+        drop(res.take_reader());
+        assert!(events.send(&Event::new("Hello")).is_err());
+    }
+}
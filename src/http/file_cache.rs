@@ -0,0 +1,232 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, Metadata};
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use time;
+
+use http::headers;
+
+/// Content and metadata of a static file
+#[derive(Clone, Debug)]
+pub struct CachedFile {
+    /// Content of the file.
+    pub body: Arc<Vec<u8>>,
+
+    /// Size of the file in bytes.
+    pub size: u64,
+
+    /// Time of the last modification of the file.
+    pub modified: SystemTime,
+
+    /// Entity tag of the file, like `"1f4-5f2b1c3a"`.
+    pub etag: String,
+
+    /// Time of the last modification of the file as an HTTP-date.
+    pub last_modified: String
+}
+
+impl CachedFile {
+    /// Read the file at `path`.
+    pub fn read(path: &Path) -> io::Result<CachedFile> {
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() {
+            return Err(io::Error::other("Not a file"));
+        }
+
+        let mut body = Vec::with_capacity(metadata.len() as usize);
+        file.read_to_end(&mut body)?;
+
+        let mut cached_file = CachedFile::from_metadata(&metadata);
+        cached_file.size = body.len() as u64;
+        cached_file.body = Arc::new(body);
+        Ok(cached_file)
+    }
+
+    /// Create a file without content from its `metadata`.
+    pub fn from_metadata(metadata: &Metadata) -> CachedFile {
+        let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+        let seconds = modified.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let tm = time::at_utc(time::Timespec::new(seconds as i64, 0));
+
+        CachedFile {
+            body: Arc::new(Vec::new()),
+            size: metadata.len(),
+            modified,
+            etag: format!("\"{:x}-{:x}\"", metadata.len(), seconds),
+            last_modified: headers::http_date(&tm)
+        }
+    }
+
+    /// Return `true` if the file has not changed since it was read,
+    /// according to its current `metadata`.
+    pub fn is_fresh(&self, metadata: &Metadata) -> bool {
+        metadata.len() == self.size && metadata.modified().ok() == Some(self.modified)
+    }
+}
+
+/// Bounded cache of static files, evicting the least recently used files
+/// first, shared between the threads of the server.
+#[derive(Clone)]
+pub struct FileCache {
+    /// Maximum total size in bytes of the files in the cache.
+    pub max_size: usize,
+
+    /// Maximum size in bytes of a file to be cached.
+    pub max_entry_size: usize,
+
+    entries: Arc<Mutex<Entries>>
+}
+
+#[derive(Default)]
+struct Entries {
+    files: HashMap<PathBuf, (CachedFile, u64)>,
+    recently_used: BTreeMap<u64, PathBuf>,
+    size: usize,
+    tick: u64
+}
+
+impl FileCache {
+    pub fn new(max_size: usize, max_entry_size: usize) -> FileCache {
+        FileCache {
+            max_size,
+            max_entry_size,
+            entries: Arc::new(Mutex::new(Entries::default()))
+        }
+    }
+
+    /// Get the file at `path` from the cache, or from the disk if it
+    /// is not in the cache or has been modified since it was cached.
+    pub fn get(&self, path: &Path) -> io::Result<CachedFile> {
+        let metadata = fs::metadata(path)?;
+
+        {
+            let mut entries = self.entries.lock().unwrap();
+            let fresh = match entries.files.get(path) {
+                Some((file, _)) => file.is_fresh(&metadata),
+                None            => false
+            };
+            if fresh {
+                return Ok(entries.touch(path));
+            }
+            entries.remove(path);
+        }
+
+        let file = CachedFile::read(path)?;
+        if file.body.len() <= self.max_entry_size && file.body.len() <= self.max_size {
+            let mut entries = self.entries.lock().unwrap();
+            entries.remove(path);
+            while entries.size + file.body.len() > self.max_size {
+                entries.evict();
+            }
+            entries.insert(path, file.clone());
+        }
+
+        Ok(file)
+    }
+
+    /// Get the number of files in the cache and their total size in bytes.
+    pub fn usage(&self) -> (usize, usize) {
+        let entries = self.entries.lock().unwrap();
+        (entries.files.len(), entries.size)
+    }
+}
+
+impl Entries {
+    fn insert(&mut self, path: &Path, file: CachedFile) {
+        self.tick += 1;
+        self.size += file.body.len();
+        self.recently_used.insert(self.tick, path.to_path_buf());
+        self.files.insert(path.to_path_buf(), (file, self.tick));
+    }
+
+    fn remove(&mut self, path: &Path) {
+        if let Some((file, tick)) = self.files.remove(path) {
+            self.size -= file.body.len();
+            self.recently_used.remove(&tick);
+        }
+    }
+
+    /// Mark the file at `path` as the most recently used and return it.
+    fn touch(&mut self, path: &Path) -> CachedFile {
+        self.tick += 1;
+        let tick = self.tick;
+        let entry = self.files.get_mut(path).unwrap();
+        self.recently_used.remove(&entry.1);
+        self.recently_used.insert(tick, path.to_path_buf());
+        entry.1 = tick;
+        entry.0.clone()
+    }
+
+    /// Remove the least recently used file.
+    fn evict(&mut self) {
+        let path = match self.recently_used.values().next() {
+            Some(path) => path.clone(),
+            None       => return
+        };
+        self.remove(&path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    fn temp_file(name: &str, content: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("simpleton-{}-{}", name, time::precise_time_ns()));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_get() {
+        let path = temp_file("cache-get", "Hello, World!");
+        let cache = FileCache::new(1024, 1024);
+
+        let file = cache.get(&path).unwrap();
+        assert_eq!(*file.body, b"Hello, World!".to_vec());
+        assert_eq!(cache.usage(), (1, 13));
+
+        // The file is invalidated when its size changes
+        fs::write(&path, "Hello!").unwrap();
+        let file = cache.get(&path).unwrap();
+        assert_eq!(*file.body, b"Hello!".to_vec());
+        assert_eq!(cache.usage(), (1, 6));
+
+        fs::remove_file(&path).unwrap();
+        assert!(cache.get(&path).is_err());
+    }
+
+    #[test]
+    fn test_limits() {
+        let a = temp_file("cache-a", "aaaa");
+        let b = temp_file("cache-b", "bbbb");
+        let c = temp_file("cache-c", "cccccccc");
+        let cache = FileCache::new(8, 4);
+
+        cache.get(&a).unwrap();
+        cache.get(&b).unwrap();
+        assert_eq!(cache.usage(), (2, 8));
+
+        // Too large to be cached
+        cache.get(&c).unwrap();
+        assert_eq!(cache.usage(), (2, 8));
+
+        // The least recently used file is evicted
+        cache.get(&a).unwrap();
+        fs::write(&c, "cccc").unwrap();
+        cache.get(&c).unwrap();
+        assert_eq!(cache.usage(), (2, 8));
+        assert!(cache.entries.lock().unwrap().files.contains_key(&a));
+        assert!(!cache.entries.lock().unwrap().files.contains_key(&b));
+
+        for path in &[a, b, c] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use std::net::IpAddr;
+
+use http::cidr::{self, Cidr};
+use http::headers::Headers;
+use http::request::Request;
+
+/// Information about a hop of a request added by a proxy, from the client
+/// to the nearest proxy
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Hop {
+    /// Address of the node making the request to the proxy, or `None` if it
+    /// is unknown or obfuscated.
+    pub node: Option<IpAddr>,
+
+    /// Scheme of the request received by the proxy, like `https`.
+    pub proto: Option<String>,
+
+    /// Host header of the request received by the proxy.
+    pub host: Option<String>
+}
+
+/// Parse the elements of a `Forwarded` header, like
+/// `for=192.0.2.60;proto=https, for="[2001:db8::1]:4711"`.
+///
+/// (RFC 7239 4)
+pub fn parse_forwarded(value: &str) -> Vec<Hop> {
+    split_quoted(value, ',').iter().map(|element| {
+        let mut hop = Hop::default();
+        for pair in split_quoted(element, ';') {
+            let (name, value) = match pair.split_once('=') {
+                Some((name, value)) => (name.trim(), unquote(value.trim())),
+                None                => continue
+            };
+            match name.to_lowercase().as_str() {
+                "for"   => hop.node = parse_node(&value),
+                "proto" => hop.proto = Some(value),
+                "host"  => hop.host = Some(value),
+                _       => {}
+            }
+        }
+        hop
+    }).collect()
+}
+
+/// Get the hops of the `X-Forwarded-For`, `X-Forwarded-Proto` and
+/// `X-Forwarded-Host` headers. The scheme and the host are given to every
+/// hop when their lists don't have one item per address, since they are
+/// usually replaced rather than appended by the proxies.
+pub fn parse_x_forwarded(headers: &Headers) -> Vec<Hop> {
+    let list = |name| -> Vec<String> {
+        headers.get(name).map_or(vec![], |value| {
+            value.split(',').map(|item| item.trim().to_string()).collect()
+        })
+    };
+    let nodes = list("x-forwarded-for");
+    let protos = list("x-forwarded-proto");
+    let hosts = list("x-forwarded-host");
+
+    let n = nodes.len().max(if protos.is_empty() && hosts.is_empty() { 0 } else { 1 });
+    let item = |items: &[String], i: usize| {
+        if items.len() == n { items.get(i).cloned() } else { items.last().cloned() }
+    };
+    (0..n).map(|i| Hop {
+        node: nodes.get(i).and_then(|node| parse_node(node)),
+        proto: item(&protos, i),
+        host: item(&hosts, i)
+    }).collect()
+}
+
+/// Replace the address of the client, the scheme and the host of a request
+/// received from one of `trusted_proxies` by those forwarded in its headers,
+/// keeping the address of the proxy in `Request::peer_ip`.
+///
+/// The hops of the `Forwarded` header, or else of the `X-Forwarded-*`
+/// headers, are walked from the nearest proxy while their node is trusted
+/// too, and the client is the node of the last hop reached.
+///
+/// (RFC 7239 5.2, 8.1)
+pub fn resolve(req: &mut Request, trusted_proxies: &[Cidr]) {
+    let is_trusted = |ip: &IpAddr| trusted_proxies.iter().any(|cidr| cidr.contains(ip));
+    match req.peer_ip.parse::<IpAddr>() {
+        Ok(ref ip) if is_trusted(ip) => {},
+        _                            => return
+    }
+
+    let hops = match req.headers.get("forwarded") {
+        Some(value) => parse_forwarded(value),
+        None        => parse_x_forwarded(&req.headers)
+    };
+    let mut client = None;
+    for hop in hops.iter().rev() {
+        client = Some(hop);
+        match hop.node {
+            Some(ref ip) if is_trusted(ip) => continue,
+            _                              => break
+        }
+    }
+    let hop = match client {
+        None      => return,
+        Some(hop) => hop
+    };
+
+    if let Some(ip) = hop.node {
+        req.ip = cidr::normalize(ip).to_string();
+    }
+    if let Some(ref proto) = hop.proto {
+        let proto = proto.to_lowercase();
+        if proto == "http" || proto == "https" {
+            req.scheme = proto;
+        }
+    }
+    if let Some(ref host) = hop.host {
+        if !host.is_empty() && !host.contains(|c: char| c.is_whitespace() || c.is_control() || c == '/') {
+            req.headers.set("host", host);
+        }
+    }
+}
+
+/// Parse the address of a node, like `192.0.2.60`, `192.0.2.60:4711`,
+/// `2001:db8::1` or `[2001:db8::1]:4711`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    match node.strip_prefix('[') {
+        Some(rest) => rest.split(']').next()?.parse().ok(),
+        None       => node.split(':').next()?.parse().ok()
+    }
+}
+
+/// Split `value` on the separator, except inside quoted strings.
+fn split_quoted(value: &str, separator: char) -> Vec<String> {
+    let mut items = vec![];
+    let mut item = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in value.chars() {
+        if escaped {
+            escaped = false;
+        } else if quoted && c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            quoted = !quoted;
+        } else if c == separator && !quoted {
+            items.push(item.trim().to_string());
+            item.clear();
+            continue;
+        }
+        item.push(c);
+    }
+    items.push(item.trim().to_string());
+
+    items
+}
+
+/// Remove the quotes and the escaping of a quoted string.
+fn unquote(value: &str) -> String {
+    match value.strip_prefix('"').and_then(|value| value.strip_suffix('"')) {
+        None        => value.into(),
+        Some(inner) => {
+            let mut unquoted = String::new();
+            let mut chars = inner.chars();
+            while let Some(c) = chars.next() {
+                unquoted.push(if c == '\\' { chars.next().unwrap_or(c) } else { c });
+            }
+            unquoted
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trusted_proxies() -> Vec<Cidr> {
+        vec![Cidr::parse("10.0.0.0/8").unwrap(), Cidr::parse("fd00::/8").unwrap()]
+    }
+
+    #[test]
+    fn test_parse_forwarded() {
+        let hops = parse_forwarded("for=192.0.2.60;proto=https;host=\"example.com\", For=\"[2001:db8:cafe::17]:4711\", for=unknown;by=10.0.0.1");
+        assert_eq!(hops.len(), 3);
+        assert_eq!(hops[0], Hop {
+            node: Some("192.0.2.60".parse().unwrap()),
+            proto: Some("https".into()),
+            host: Some("example.com".into())
+        });
+        assert_eq!(hops[1].node, Some("2001:db8:cafe::17".parse().unwrap()));
+        assert_eq!(hops[2], Hop::default());
+
+        let hops = parse_forwarded("for=\"192.0.2.60:80\";host=\"a,b\\\"c\"");
+        assert_eq!(hops.len(), 1);
+        assert_eq!(hops[0].node, Some("192.0.2.60".parse().unwrap()));
+        assert_eq!(hops[0].host, Some("a,b\"c".into()));
+    }
+
+    #[test]
+    fn test_parse_x_forwarded() {
+        let mut headers = Headers::new();
+        headers.set("x-forwarded-for", "192.0.2.60, 2001:db8::1, 10.0.0.2");
+        headers.set("x-forwarded-proto", "https");
+        let hops = parse_x_forwarded(&headers);
+        assert_eq!(hops.len(), 3);
+        assert_eq!(hops[1].node, Some("2001:db8::1".parse().unwrap()));
+        assert!(hops.iter().all(|hop| hop.proto == Some("https".into())));
+
+        headers.set("x-forwarded-host", "a.example.com, b.example.com, c.example.com");
+        assert_eq!(parse_x_forwarded(&headers)[1].host, Some("b.example.com".into()));
+
+        let mut headers = Headers::new();
+        headers.set("x-forwarded-proto", "https");
+        assert_eq!(parse_x_forwarded(&headers), vec![Hop { proto: Some("https".into()), ..Hop::default() }]);
+        assert_eq!(parse_x_forwarded(&Headers::new()), vec![]);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let mut req = Request::new("GET", "10.0.0.1", "/");
+        req.peer_ip = "10.0.0.1".into();
+        req.ip = req.peer_ip.clone();
+        req.headers.set("x-forwarded-for", "198.51.100.1, 192.0.2.60, 10.0.0.2");
+        req.headers.set("x-forwarded-proto", "https");
+        req.headers.set("x-forwarded-host", "example.com");
+        resolve(&mut req, &trusted_proxies());
+        assert_eq!(req.ip, "192.0.2.60");
+        assert_eq!(req.peer_ip, "10.0.0.1");
+        assert_eq!(req.scheme, "https");
+        assert_eq!(req.headers.get("host"), Some(&"example.com".into()));
+
+        // The Forwarded header is preferred to the X-Forwarded-* headers
+        let mut req = Request::new("GET", "10.0.0.1", "/");
+        req.peer_ip = "::ffff:10.0.0.1".into();
+        req.ip = req.peer_ip.clone();
+        req.headers.set("x-forwarded-for", "198.51.100.1");
+        req.headers.set("forwarded", "for=\"[::ffff:192.0.2.60]\";proto=HTTPS, for=\"[fd00::2]\";proto=http");
+        resolve(&mut req, &trusted_proxies());
+        assert_eq!(req.ip, "192.0.2.60");
+        assert_eq!(req.scheme, "https");
+
+        // The headers of an untrusted peer are ignored
+        let mut req = Request::new("GET", "example.com", "/");
+        req.peer_ip = "192.0.2.1".into();
+        req.ip = req.peer_ip.clone();
+        req.headers.set("x-forwarded-for", "198.51.100.1");
+        req.headers.set("x-forwarded-proto", "https");
+        resolve(&mut req, &trusted_proxies());
+        assert_eq!(req.ip, "192.0.2.1");
+        assert_eq!(req.scheme, "http");
+
+        // Invalid schemes and hosts are ignored
+        let mut req = Request::new("GET", "example.com", "/");
+        req.peer_ip = "10.0.0.1".into();
+        req.ip = req.peer_ip.clone();
+        req.headers.set("forwarded", "for=unknown;proto=ftp;host=\"a b\"");
+        resolve(&mut req, &trusted_proxies());
+        assert_eq!(req.ip, "10.0.0.1");
+        assert_eq!(req.scheme, "http");
+        assert_eq!(req.headers.get("host"), Some(&"example.com".into()));
+    }
+}
@@ -0,0 +1,169 @@
+/// Return `true` if `path` matches the glob `pattern`.
+///
+/// In the pattern `?` matches any character except `/`, `*` matches any
+/// sequence of characters except `/`, and `**` matches any sequence of
+/// characters including `/` (`**/` can also match no directory at all).
+pub fn matches(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let path: Vec<char> = path.chars().collect();
+
+    Table::new(&pattern, &path).get(0, 0)
+}
+
+/// Get the parts of `path` matched by each wildcard of the glob `pattern`,
+/// or `None` if it does not match. A wildcard matches as few characters
+/// as possible, and `**/` no directory at all if it can.
+pub fn captures(pattern: &str, path: &str) -> Option<Vec<String>> {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let path: Vec<char> = path.chars().collect();
+
+    let table = Table::new(&pattern, &path);
+    if !table.get(0, 0) {
+        return None;
+    }
+
+    // Follow the matches of the table from the start of the pattern
+    let mut captures = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < pattern.len() {
+        match pattern[i] {
+            '*' if pattern.get(i + 1) == Some(&'*') => {
+                let rest = i + 2;
+                if pattern.get(rest) == Some(&'/') && table.get(rest + 1, j) {
+                    captures.push(String::new());
+                    i = rest + 1;
+                } else {
+                    let end = (j..path.len() + 1).find(|&end| table.get(rest, end)).unwrap();
+                    captures.push(path[j..end].iter().collect());
+                    i = rest;
+                    j = end;
+                }
+            },
+            '*' => {
+                let end = (j..path.len() + 1).find(|&end| table.get(i + 1, end)).unwrap();
+                captures.push(path[j..end].iter().collect());
+                i += 1;
+                j = end;
+            },
+            '?' => {
+                captures.push(path[j].to_string());
+                i += 1;
+                j += 1;
+            },
+            _ => {
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+
+    Some(captures)
+}
+
+/// Table of the matches of each suffix of a pattern with each suffix of a
+/// path, computed in a time proportional to the product of their lengths
+/// instead of trying every split of the path between the wildcards.
+struct Table {
+    width: usize,
+    matches: Vec<bool>
+}
+
+impl Table {
+    fn new(pattern: &[char], path: &[char]) -> Table {
+        let width = path.len() + 1;
+        let mut table = Table { width, matches: vec![false; (pattern.len() + 1) * width] };
+        table.set(pattern.len(), path.len(), true);
+
+        for i in (0..pattern.len()).rev() {
+            match pattern[i] {
+                '*' if pattern.get(i + 1) == Some(&'*') => {
+                    // Any sequence of characters followed by the rest of the
+                    // pattern, or no directory at all for `**/`
+                    let rest = i + 2;
+                    let mut any = false;
+                    for j in (0..width).rev() {
+                        any = any || table.get(rest, j);
+                        let no_directory = pattern.get(rest) == Some(&'/') && table.get(rest + 1, j);
+                        table.set(i, j, any || no_directory);
+                    }
+                },
+                '*' => {
+                    for j in (0..width).rev() {
+                        let more = j < path.len() && path[j] != '/' && table.get(i, j + 1);
+                        let matched = table.get(i + 1, j) || more;
+                        table.set(i, j, matched);
+                    }
+                },
+                c => {
+                    for (j, &d) in path.iter().enumerate() {
+                        let matched = if c == '?' { d != '/' } else { d == c };
+                        let matched = matched && table.get(i + 1, j + 1);
+                        table.set(i, j, matched);
+                    }
+                }
+            }
+        }
+
+        table
+    }
+
+    /// Return `true` if the pattern from `i` matches the path from `j`.
+    fn get(&self, i: usize, j: usize) -> bool {
+        self.matches[i * self.width + j]
+    }
+
+    fn set(&mut self, i: usize, j: usize, matched: bool) {
+        self.matches[i * self.width + j] = matched;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("/index.html", "/index.html"));
+        assert!(!matches("/index.html", "/index.htm"));
+
+        assert!(matches("*.html", "index.html"));
+        assert!(matches("/*.html", "/index.html"));
+        assert!(!matches("/*.html", "/docs/index.html"));
+
+        assert!(matches("/assets/**", "/assets/js/app.js"));
+        assert!(matches("/**/*.js", "/assets/js/app.js"));
+        assert!(matches("/**/*.js", "/app.js"));
+        assert!(matches("**/.git/**", "/project/.git/config"));
+
+        assert!(matches("/app.??.js", "/app.v2.js"));
+        assert!(!matches("/app.?.js", "/app./.js"));
+    }
+
+    #[test]
+    fn test_captures() {
+        assert_eq!(captures("/index.html", "/index.html"), Some(vec![]));
+        assert_eq!(captures("/index.html", "/about.html"), None);
+
+        assert_eq!(captures("/blog/*.html", "/blog/hello.html"), Some(vec!["hello".into()]));
+        assert_eq!(captures("/blog/*.html", "/blog/2020/hello.html"), None);
+
+        let expected = vec!["2020/08".into(), "hello".into()];
+        assert_eq!(captures("/blog/**/*.html", "/blog/2020/08/hello.html"), Some(expected));
+        let expected = vec!["".into(), "hello".into()];
+        assert_eq!(captures("/blog/**/*.html", "/blog/hello.html"), Some(expected));
+
+        assert_eq!(captures("/v?/*", "/v2/users"), Some(vec!["2".into(), "users".into()]));
+    }
+
+    #[test]
+    fn test_many_wildcards() {
+        // Without trying every split of the path between the wildcards
+        let path = format!("/{}b", "a".repeat(10_000));
+        assert!(!matches("*a*a*a*a*a*a*c", &path));
+        assert!(!matches("/**a**a**a**a**a**c", &path));
+        assert_eq!(captures("/**a**a**a**a**a**c", &path), None);
+        assert!(matches("/*a*a*a*b", &path));
+        let captures = captures("/*a*a*b", &path).unwrap();
+        assert_eq!(captures, vec!["".to_string(), "".into(), "a".repeat(9_998)]);
+    }
+}
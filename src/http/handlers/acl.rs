@@ -0,0 +1,218 @@
+use std::net::IpAddr;
+
+use http::cidr::Cidr;
+use http::request::Request;
+use http::response::Response;
+
+/// Access given to the clients matching a rule
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    Allow,
+    Deny
+}
+
+/// Rule allowing or denying the clients with an address in a block
+#[derive(Clone, Debug, PartialEq)]
+pub struct AclRule {
+    pub access: Access,
+
+    /// Block of the addresses of the clients, or `None` for any client.
+    pub cidr: Option<Cidr>
+}
+
+impl AclRule {
+    /// Return `true` if the rule applies to a client with the address `ip`.
+    pub fn matches(&self, ip: Option<&IpAddr>) -> bool {
+        match (self.cidr, ip) {
+            (None, _)              => true,
+            (Some(cidr), Some(ip)) => cidr.contains(ip),
+            (Some(_), None)        => false
+        }
+    }
+}
+
+/// Rules applying to the paths starting with a prefix, like `/admin`,
+/// matching the path itself and the paths below it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Acl {
+    pub path_prefix: String,
+    pub rules: Vec<AclRule>
+}
+
+impl Acl {
+    /// Return `true` if the ACL applies to `path`.
+    pub fn applies_to(&self, path: &str) -> bool {
+        let prefix = self.path_prefix.trim_end_matches('/');
+        prefix.is_empty() || path == prefix || path.starts_with(&format!("{}/", prefix))
+    }
+
+    /// Return the access given by the first rule matching the address `ip`
+    /// of a client, allowing it when no rule matches.
+    pub fn access(&self, ip: Option<&IpAddr>) -> Access {
+        self.rules.iter().find(|rule| rule.matches(ip)).map_or(Access::Allow, |rule| rule.access)
+    }
+}
+
+/// Parse the rules of a configuration file, one per line, ignoring empty
+/// lines and comments starting with `#`. A rule is a path prefix, an access
+/// and a CIDR block or `all`, like `/admin allow 10.0.0.0/8`, and the rules
+/// of a prefix are kept in the order of the file.
+pub fn parse_acls(text: &str) -> Result<Vec<Acl>, String> {
+    let mut acls: Vec<Acl> = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 3 || !fields[0].starts_with('/') {
+            return Err(format!("Could not parse rule '{}' (line {})", line, i + 1));
+        }
+        let access = match fields[1] {
+            "allow" => Access::Allow,
+            "deny"  => Access::Deny,
+            _ => return Err(format!("Could not parse rule access '{}' (line {})", fields[1], i + 1))
+        };
+        let cidr = match fields[2] {
+            "all" => None,
+            cidr  => Some(Cidr::parse(cidr).map_err(|e| format!("{} (line {})", e, i + 1))?)
+        };
+
+        let rule = AclRule { access, cidr };
+        match acls.iter_mut().find(|acl| acl.path_prefix == fields[0]) {
+            Some(acl) => acl.rules.push(rule),
+            None      => acls.push(Acl { path_prefix: fields[0].into(), rules: vec![rule] })
+        }
+    }
+
+    Ok(acls)
+}
+
+/// Deny access to the requests from the clients denied by the ACL of
+/// `Server::acls` with the longest prefix applying to their path.
+///
+/// The address of the client is `Request::ip`, forwarded by the proxy when
+/// it is one of `Server::trusted_proxies`, and otherwise the address of the
+/// peer of the connection. With an empty `Server::trusted_proxies`, the
+/// rules never match an address taken from the `Forwarded` or
+/// `X-Forwarded-For` headers.
+///
+/// The handler must come after the `rewrite` handler, or be added again
+/// after it, for the ACLs to apply to the rewritten path.
+pub fn handler(req: Request, mut res: Response) -> Response {
+    if res.is_ended() {
+        return res;
+    }
+
+    let server = res.server.clone();
+    let path = req.decoded_path();
+    let acl = server.acls.iter().filter(|acl| acl.applies_to(&path)).max_by_key(|acl| {
+        acl.path_prefix.trim_end_matches('/').len()
+    });
+    let acl = match acl {
+        None      => return res,
+        Some(acl) => acl
+    };
+
+    let ip = req.ip.parse::<IpAddr>().ok();
+    if acl.access(ip.as_ref()) == Access::Deny {
+        res.set_status(403);
+        res.end();
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use http::handlers::rewrite;
+    use http::server::Server;
+
+    #[test]
+    fn test_parse_acls() {
+        let text = "# Admin\n/admin allow 10.0.0.0/8\n/ deny 192.0.2.1\n/admin allow 2001:db8::/32\n/admin deny all\n";
+        let acls = parse_acls(text).unwrap();
+        assert_eq!(acls.len(), 2);
+        assert_eq!(acls[0].path_prefix, "/admin");
+        assert_eq!(acls[0].rules.len(), 3);
+        assert_eq!(acls[0].rules[2], AclRule { access: Access::Deny, cidr: None });
+        assert_eq!(acls[1].rules[0].cidr, Some(Cidr::parse("192.0.2.1/32").unwrap()));
+
+        assert!(parse_acls("/admin permit all").is_err());
+        assert!(parse_acls("/admin allow 10.0.0.0/40").is_err());
+        assert!(parse_acls("admin allow all").is_err());
+        assert!(parse_acls("/admin allow").is_err());
+    }
+
+    #[test]
+    fn test_access() {
+        let acls = parse_acls("/ allow 10.0.0.0/8\n/ deny 10.1.0.0/16\n/ deny ::/0").unwrap();
+        let ip = |s: &str| s.parse::<IpAddr>().ok();
+        assert_eq!(acls[0].access(ip("10.1.2.3").as_ref()), Access::Allow);
+        assert_eq!(acls[0].access(ip("::ffff:10.1.2.3").as_ref()), Access::Allow);
+        assert_eq!(acls[0].access(ip("2001:db8::1").as_ref()), Access::Deny);
+        assert_eq!(acls[0].access(ip("192.0.2.1").as_ref()), Access::Allow);
+        assert_eq!(acls[0].access(None), Access::Allow);
+    }
+
+    #[test]
+    fn test_handler() {
+        let mut server = Server::new();
+        server.acls = parse_acls("/ deny 192.0.2.0/24\n/admin/ allow 127.0.0.1\n/admin/ deny all").unwrap();
+
+        let mut req = Request::new("GET", "example.com", "/index.html");
+        req.ip = "192.0.2.1".into();
+        let res = handler(req, Response::new(server.clone()));
+        assert_eq!(res.status_code, 403);
+
+        let mut req = Request::new("GET", "example.com", "/index.html");
+        req.ip = "198.51.100.1".into();
+        let res = handler(req, Response::new(server.clone()));
+        assert!(!res.is_ended());
+
+        let mut req = Request::new("GET", "example.com", "/public/../admin");
+        req.ip = "198.51.100.1".into();
+        let res = handler(req, Response::new(server.clone()));
+        assert_eq!(res.status_code, 403);
+
+        let mut req = Request::new("GET", "example.com", "/%61dmin/index.html");
+        req.ip = "198.51.100.1".into();
+        let res = handler(req, Response::new(server.clone()));
+        assert_eq!(res.status_code, 403);
+
+        let mut req = Request::new("GET", "example.com", "/admin/index.html");
+        req.ip = "127.0.0.1".into();
+        let res = handler(req, Response::new(server.clone()));
+        assert!(!res.is_ended());
+
+        let mut req = Request::new("GET", "example.com", "/administrator");
+        req.ip = "127.0.0.1".into();
+        let res = handler(req, Response::new(server));
+        assert!(!res.is_ended());
+    }
+
+    #[test]
+    fn test_handler_with_rewrite() {
+        let mut server = Server::new();
+        server.acls = parse_acls("/admin allow 127.0.0.1\n/admin deny all").unwrap();
+        server.rewrite_rules = rewrite::parse_rules("rewrite /old/* /admin/$1").unwrap();
+        let chain = |req: Request| {
+            let mut res = handler(req.clone(), Response::new(server.clone()));
+            let req = res.take_updated_request().unwrap_or(req);
+            let mut res = rewrite::handler(req.clone(), res);
+            let req = res.take_updated_request().unwrap_or(req);
+            handler(req, res)
+        };
+
+        let mut req = Request::new("GET", "example.com", "/old/index.html");
+        req.ip = "198.51.100.1".into();
+        assert_eq!(chain(req).status_code, 403);
+
+        let mut req = Request::new("GET", "example.com", "/old/index.html");
+        req.ip = "127.0.0.1".into();
+        assert!(!chain(req).is_ended());
+    }
+}
@@ -0,0 +1,358 @@
+use std::fs;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use bcrypt;
+use md5;
+use sha1_smol::Sha1;
+
+use http::request::Request;
+use http::response::Response;
+use http::server::Server;
+
+/// Alphabet of the encoding of the hashes of the `apr1` algorithm.
+const APR1_ALPHABET: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Users and password hashes of an Apache `htpasswd` file
+#[derive(Clone, Debug, Default)]
+pub struct Htpasswd {
+    entries: Vec<(String, String)>
+}
+
+impl Htpasswd {
+    /// Parse the lines `user:hash` of an `htpasswd` file, ignoring empty
+    /// lines and comments starting with `#`.
+    pub fn parse(text: &str) -> Result<Htpasswd, String> {
+        let mut entries = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once(':') {
+                Some((user, hash)) if !user.is_empty() => entries.push((user.into(), hash.into())),
+                _ => return Err(format!("Could not parse htpasswd entry (line {})", i + 1))
+            }
+        }
+
+        Ok(Htpasswd { entries })
+    }
+
+    pub fn from_file(path: &str) -> Result<Htpasswd, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Htpasswd::parse(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    /// Return `true` if `password` matches the hash of `user`, with the
+    /// bcrypt (`$2y$`), SHA-1 (`{SHA}`) or MD5 (`$apr1$`) algorithm.
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        let hash = match self.entries.iter().find(|&(name, _)| name == user) {
+            Some((_, hash)) => hash,
+            None            => return false
+        };
+
+        if hash.starts_with("$2y$") || hash.starts_with("$2a$") || hash.starts_with("$2b$") {
+            bcrypt::verify(password, hash).unwrap_or(false)
+        } else if let Some(digest) = hash.strip_prefix("{SHA}") {
+            let expected = BASE64.encode(Sha1::from(password).digest().bytes());
+            constant_time_eq(expected.as_bytes(), digest.as_bytes())
+        } else if let Some(rest) = hash.strip_prefix("$apr1$") {
+            let salt = rest.split('$').next().unwrap();
+            constant_time_eq(apr1(password, salt).as_bytes(), hash.as_bytes())
+        } else {
+            // Plaintext and crypt(3) hashes are not supported
+            false
+        }
+    }
+}
+
+/// Configuration of the HTTP Basic authentication
+#[derive(Clone, Debug)]
+pub struct BasicAuth {
+    /// Protection space given to the clients in the challenge.
+    pub realm: String,
+
+    /// Path prefixes of the protected requests, like `/private`, matching
+    /// the path itself and the paths below it.
+    pub path_prefixes: Vec<String>,
+
+    pub users: Htpasswd
+}
+
+impl BasicAuth {
+    /// Create an authentication of the users of `htpasswd` protecting
+    /// every path.
+    pub fn new(users: Htpasswd) -> BasicAuth {
+        BasicAuth {
+            realm: "Restricted".into(),
+            path_prefixes: vec!["/".into()],
+            users
+        }
+    }
+}
+
+/// Return `true` if `path` is one of `path_prefixes` or is below one of them.
+pub fn is_protected(path_prefixes: &[String], path: &str) -> bool {
+    protecting_prefix_len(path_prefixes, path).is_some()
+}
+
+/// Get the length of the longest of `path_prefixes` protecting `path`.
+fn protecting_prefix_len(path_prefixes: &[String], path: &str) -> Option<usize> {
+    path_prefixes.iter().map(|prefix| prefix.trim_end_matches('/')).filter(|prefix| {
+        prefix.is_empty() || path == *prefix || path.starts_with(&format!("{}/", prefix))
+    }).map(|prefix| prefix.len()).max()
+}
+
+/// Get the schemes required by the server for `path`, `digest` and `basic`:
+/// the one of the authentication with the longest prefix protecting it, or
+/// both of them for the same prefix, the client then using any of them.
+/// A user of one authentication is not a user of the other one.
+pub fn required_schemes(server: &Server, path: &str) -> Vec<&'static str> {
+    let digest = server.digest_auth.as_ref().and_then(|auth| protecting_prefix_len(&auth.path_prefixes, path));
+    let basic = server.basic_auth.as_ref().and_then(|auth| protecting_prefix_len(&auth.path_prefixes, path));
+
+    let mut schemes = vec![];
+    if digest.is_some() && digest >= basic {
+        schemes.push("digest");
+    }
+    if basic.is_some() && basic >= digest {
+        schemes.push("basic");
+    }
+    schemes
+}
+
+/// Compare two byte strings in a time independent of their content.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Parse the user and the password of the value of an `Authorization`
+/// or a `Proxy-Authorization` header with the `Basic` scheme.
+///
+/// (RFC 7617 2)
+pub fn parse_credentials(value: &str) -> Option<(String, String)> {
+    let (scheme, token) = value.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = BASE64.decode(token.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+
+    Some((user.into(), password.into()))
+}
+
+/// Get the scheme of the `Authorization` header of `req` in lowercase,
+/// like `basic` or `digest`.
+pub fn authorization_scheme(req: &Request) -> Option<String> {
+    req.headers.get("authorization").and_then(|value| {
+        value.split_whitespace().next().map(|scheme| scheme.to_lowercase())
+    })
+}
+
+/// End the response to a request for `path` with a `401` status code and
+/// the challenges of every scheme required by the server for it, the Digest
+/// ones first since it is the stronger scheme, so that a client can use
+/// any of them. The Digest challenges are marked as `stale` when the nonce
+/// of valid credentials has expired.
+///
+/// (RFC 7235 4.1)
+pub fn unauthorized(res: &mut Response, path: &str, stale: bool) {
+    let server = res.server.clone();
+    let schemes = required_schemes(&server, path);
+    let mut challenges = vec![];
+    if let Some(ref auth) = server.digest_auth {
+        if schemes.contains(&"digest") {
+            challenges.push(auth.challenges(stale));
+        }
+    }
+    if let Some(ref auth) = server.basic_auth {
+        if schemes.contains(&"basic") {
+            let realm = auth.realm.replace('\\', "\\\\").replace('"', "\\\"");
+            challenges.push(format!("Basic realm=\"{}\", charset=\"UTF-8\"", realm));
+        }
+    }
+
+    res.set_status(401);
+    res.headers.set("www-authenticate", &challenges.join(", "));
+    res.end();
+}
+
+/// Compute the hash of `password` with the `apr1` algorithm of Apache,
+/// a variant of the MD5-based crypt(3) of FreeBSD.
+fn apr1(password: &str, salt: &str) -> String {
+    let password = password.as_bytes();
+    let salt = &salt.as_bytes()[..salt.len().min(8)];
+    let magic = b"$apr1$";
+
+    let mut alternate = md5::Context::new();
+    alternate.consume(password);
+    alternate.consume(salt);
+    alternate.consume(password);
+    let alternate = alternate.finalize();
+
+    let mut context = md5::Context::new();
+    context.consume(password);
+    context.consume(magic);
+    context.consume(salt);
+    for chunk in password.chunks(16) {
+        context.consume(&alternate[..chunk.len()]);
+    }
+    let mut i = password.len();
+    while i > 0 {
+        if i & 1 == 1 {
+            context.consume([0]);
+        } else {
+            context.consume(&password[..1]);
+        }
+        i >>= 1;
+    }
+    let mut digest = context.finalize();
+
+    for i in 0..1000 {
+        let mut context = md5::Context::new();
+        if i & 1 == 1 { context.consume(password) } else { context.consume(*digest) }
+        if i % 3 != 0 { context.consume(salt) }
+        if i % 7 != 0 { context.consume(password) }
+        if i & 1 == 1 { context.consume(*digest) } else { context.consume(password) }
+        digest = context.finalize();
+    }
+
+    let mut hash = String::from("$apr1$");
+    hash.push_str(&String::from_utf8_lossy(salt));
+    hash.push('$');
+    let groups = [(0, 6, 12), (1, 7, 13), (2, 8, 14), (3, 9, 15), (4, 10, 5)];
+    for &(a, b, c) in &groups {
+        let value = (digest[a] as u32) << 16 | (digest[b] as u32) << 8 | digest[c] as u32;
+        push_apr1_chars(&mut hash, value, 4);
+    }
+    push_apr1_chars(&mut hash, digest[11] as u32, 2);
+
+    hash
+}
+
+fn push_apr1_chars(hash: &mut String, mut value: u32, n: usize) {
+    for _ in 0..n {
+        hash.push(APR1_ALPHABET[(value & 0x3F) as usize] as char);
+        value >>= 6;
+    }
+}
+
+/// Require the credentials of a user of `Server::basic_auth` for the
+/// requests for which the server requires the Basic scheme, and give the
+/// authenticated user to the following handlers. Credentials of the Digest
+/// scheme are left to the `digest_auth` handler when it is also required.
+///
+/// (RFC 7235 3.1)
+pub fn handler(req: Request, mut res: Response) -> Response {
+    if res.is_ended() {
+        return res;
+    }
+
+    let server = res.server.clone();
+    let auth = match server.basic_auth {
+        None           => return res,
+        Some(ref auth) => auth
+    };
+    // Applications behind a proxy or a gateway decode the path
+    let path = req.decoded_path();
+    let schemes = required_schemes(&server, &path);
+    if !schemes.contains(&"basic") {
+        return res;
+    }
+
+    // Skip the users already authenticated with this scheme, like before
+    // an internal redirect, and the Digest credentials left to the
+    // `digest_auth` handler.
+    let scheme = authorization_scheme(&req);
+    let is_digest = scheme.as_deref() == Some("digest") && schemes.contains(&"digest");
+    if req.auth_scheme.as_deref() == Some("basic") || is_digest {
+        return res;
+    }
+
+    let credentials = req.headers.get("authorization").and_then(|value| parse_credentials(value));
+    match credentials {
+        Some((ref user, ref password)) if auth.users.verify(user, password) => {
+            let mut req = req.clone();
+            req.user = Some(user.clone());
+            req.auth_scheme = Some("basic".into());
+            res.update_request(req);
+        },
+        _ => unauthorized(&mut res, &path, false)
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use http::server::Server;
+
+    #[test]
+    fn test_apr1() {
+        // Generated with `openssl passwd -apr1 -salt r31.... secret`
+        assert_eq!(apr1("secret", "r31...."), "$apr1$r31....$gnsoqlxyxQQ0Ot5JCwiei.");
+    }
+
+    #[test]
+    fn test_verify() {
+        let bcrypt_hash = bcrypt::hash("bcrypt-secret", 4).unwrap().replacen("$2b$", "$2y$", 1);
+        let text = format!(
+            "# Users\nalice:$apr1$r31....$gnsoqlxyxQQ0Ot5JCwiei.\nbob:{{SHA}}5en6G6MezRroT3XKqkdPOmY/BfQ=\ncarol:{}\ndave:secret\n",
+            bcrypt_hash
+        );
+        let users = Htpasswd::parse(&text).unwrap();
+
+        assert!(users.verify("alice", "secret"));
+        assert!(!users.verify("alice", "Secret"));
+        assert!(users.verify("bob", "secret"));
+        assert!(!users.verify("bob", "secret "));
+        assert!(users.verify("carol", "bcrypt-secret"));
+        assert!(!users.verify("carol", "secret"));
+        assert!(!users.verify("dave", "secret"));
+        assert!(!users.verify("eve", "secret"));
+
+        assert!(Htpasswd::parse("alice").is_err());
+    }
+
+    #[test]
+    fn test_parse_credentials() {
+        assert_eq!(parse_credentials("Basic YWxpY2U6b3Blbjpzw6lzYW1l"), Some(("alice".into(), "open:sésame".into())));
+        assert_eq!(parse_credentials("basic YWxpY2U6"), Some(("alice".into(), "".into())));
+        assert_eq!(parse_credentials("Bearer YWxpY2U6"), None);
+        assert_eq!(parse_credentials("Basic YWxpY2U"), None);
+    }
+
+    #[test]
+    fn test_handler() {
+        let users = Htpasswd::parse("alice:{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=").unwrap();
+        let mut auth = BasicAuth::new(users);
+        auth.path_prefixes = vec!["/private/".into()];
+        let mut server = Server::new();
+        server.basic_auth = Some(auth);
+
+        let req = Request::new("GET", "example.com", "/private-notes.html");
+        let mut res = handler(req, Response::new(server.clone()));
+        assert!(!res.is_ended());
+        assert!(res.take_updated_request().is_none());
+
+        let req = Request::new("GET", "example.com", "/public/../private");
+        let res = handler(req, Response::new(server.clone()));
+        assert_eq!(res.status_code, 401);
+        assert_eq!(res.headers.get("www-authenticate"), Some(&"Basic realm=\"Restricted\", charset=\"UTF-8\"".into()));
+
+        for uri in &["/%70rivate/x", "/public/%2e%2e/private", "/public/..%2Fprivate"] {
+            let req = Request::new("GET", "example.com", uri);
+            let res = handler(req, Response::new(server.clone()));
+            assert_eq!(res.status_code, 401);
+        }
+
+        let mut req = Request::new("GET", "example.com", "/private/index.html");
+        req.headers.set("authorization", "Basic YWxpY2U6c2VjcmV0");
+        let mut res = handler(req, Response::new(server));
+        assert!(!res.is_ended());
+        assert_eq!(res.take_updated_request().unwrap().user, Some("alice".into()));
+    }
+}
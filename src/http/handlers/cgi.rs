@@ -0,0 +1,481 @@
+use std::env;
+use std::io::prelude::*;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use http::headers::Headers;
+use http::request::Request;
+use http::response::Response;
+
+/// Configuration of the execution of CGI scripts
+#[derive(Clone, Debug)]
+pub struct Cgi {
+    /// Directory containing the scripts.
+    pub dir: String,
+
+    /// Prefix of the paths of the scripts in the directory, like `/cgi-bin/`.
+    pub path_prefix: String,
+
+    /// Extensions of the files of the directory executed as scripts
+    /// whatever their path, like `cgi` or `pl`.
+    pub extensions: Vec<String>,
+
+    /// Maximum time to wait for the output of a script.
+    pub timeout: Duration
+}
+
+impl Cgi {
+    pub fn new(dir: &str) -> Cgi {
+        Cgi {
+            dir: dir.into(),
+            path_prefix: "/cgi-bin/".into(),
+            extensions: Vec::new(),
+            timeout: Duration::from_secs(30)
+        }
+    }
+
+    /// Find the script of the request at `path`, and get its file, its
+    /// `SCRIPT_NAME` and its `PATH_INFO`, or `None` if the path is not
+    /// one of a script.
+    fn find_script(&self, path: &str) -> Option<(PathBuf, String, String)> {
+        let in_prefix = path.starts_with(&self.path_prefix);
+        let (mut script_name, relative_path) = if in_prefix {
+            let base = self.path_prefix.trim_end_matches('/');
+            (base.to_string(), &path[base.len()..])
+        } else if !self.extensions.is_empty() {
+            (String::new(), path)
+        } else {
+            return None;
+        };
+
+        let segments: Vec<&str> = relative_path.split('/').filter(|s| !s.is_empty()).collect();
+        let mut file = PathBuf::from(&self.dir);
+        for (i, segment) in segments.iter().enumerate() {
+            file.push(segment);
+            script_name.push('/');
+            script_name.push_str(segment);
+
+            if file.is_file() {
+                let extension = file.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+                if !in_prefix && !self.extensions.iter().any(|ext| ext == extension) {
+                    return None;
+                }
+                let path_info: String = segments[i + 1..].iter().map(|s| format!("/{}", s)).collect();
+                return Some((file, script_name, path_info));
+            }
+            if !file.is_dir() {
+                return None;
+            }
+        }
+
+        None
+    }
+}
+
+/// Build the meta-variables of the request given to a script.
+///
+/// (RFC 3875 4.1)
+pub fn environment(req: &Request, root_path: &str, script: &Path, script_name: &str, path_info: &str) -> Vec<(String, String)> {
+    let mut vars = vec![];
+    let mut set = |name: &str, value: &str| vars.push((name.to_string(), value.to_string()));
+
+    let host = req.headers.get("host").cloned().unwrap_or_default();
+    let (server_name, server_port) = match host.rfind(':') {
+        Some(i) if !host[i..].contains(']') => (&host[..i], &host[i + 1..]),
+        _                                   => (&host[..], "80")
+    };
+    let query = req.uri.split_once('?').map_or("", |(_, query)| query);
+
+    set("GATEWAY_INTERFACE", "CGI/1.1");
+    set("SERVER_SOFTWARE", "SimpletonHTTP/0.0.0");
+    set("SERVER_PROTOCOL", &req.version);
+    set("SERVER_NAME", server_name);
+    set("SERVER_PORT", server_port);
+    set("REQUEST_METHOD", &req.method);
+    set("REQUEST_URI", &req.uri);
+    set("QUERY_STRING", query);
+    set("SCRIPT_NAME", script_name);
+    set("SCRIPT_FILENAME", script.to_str().unwrap_or(""));
+    set("DOCUMENT_ROOT", root_path);
+    set("REMOTE_ADDR", &req.ip);
+    set("REMOTE_HOST", &req.ip);
+    if !path_info.is_empty() {
+        set("PATH_INFO", path_info);
+        set("PATH_TRANSLATED", &format!("{}{}", root_path, path_info));
+    }
+    if let Some(content_type) = req.headers.get("content-type") {
+        set("CONTENT_TYPE", content_type);
+    }
+    if !req.body.is_empty() || req.headers.contains_key("content-length") {
+        set("CONTENT_LENGTH", &req.body.len().to_string());
+    }
+    if let Some(authorization) = req.headers.get("authorization") {
+        set("AUTH_TYPE", authorization.split_whitespace().next().unwrap_or(""));
+    }
+    if let Ok(path) = env::var("PATH") {
+        set("PATH", &path);
+    }
+
+    // Credentials and the headers already given as meta-variables are not
+    // passed to the scripts, nor the `Proxy` header that would set the
+    // `HTTP_PROXY` variable used by HTTP clients (httpoxy).
+    let excluded = ["authorization", "content-length", "content-type", "proxy"];
+    for (name, value) in &req.headers {
+        if !excluded.contains(&name.as_str()) {
+            set(&format!("HTTP_{}", name.to_uppercase().replace('-', "_")), &value);
+        }
+    }
+
+    vars
+}
+
+/// Run `script` with the body of the request on its standard input, and
+/// get its standard output and error, or `None` if it has not exited
+/// before the timeout.
+fn run(cgi: &Cgi, script: &Path, vars: Vec<(String, String)>, body: Vec<u8>) -> Result<Option<(Vec<u8>, String)>, String> {
+    let mut command = Command::new(script);
+    if let Some(dir) = script.parent() {
+        command.current_dir(dir);
+    }
+    let mut child = command.env_clear().envs(vars)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+
+    // The pipes are used in their own threads to avoid a deadlock with
+    // a script writing a large output before reading its input.
+    let mut stdin = child.stdin.take().unwrap();
+    thread::spawn(move || {
+        let _ = stdin.write_all(&body);
+    });
+    let mut stderr = child.stderr.take().unwrap();
+    let (errors_tx, errors_rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut errors = String::new();
+        let _ = stderr.read_to_string(&mut errors);
+        let _ = errors_tx.send(errors);
+    });
+    let mut stdout = child.stdout.take().unwrap();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut output = vec![];
+        let _ = stdout.read_to_end(&mut output);
+        let _ = tx.send(output);
+    });
+
+    let deadline = Instant::now() + cgi.timeout;
+    let remaining = || deadline.saturating_duration_since(Instant::now());
+    let output = rx.recv_timeout(remaining()).ok();
+
+    // The script can close its standard output and keep running
+    let mut exited = false;
+    while output.is_some() && !exited && Instant::now() < deadline {
+        match child.try_wait() {
+            Ok(None) => thread::sleep(Duration::from_millis(10)),
+            _        => exited = true
+        }
+    }
+    let output = match output {
+        Some(output) if exited => output,
+        _ => {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        }
+    };
+
+    // Its standard error can still be open in another process
+    let errors = errors_rx.recv_timeout(remaining()).unwrap_or_default();
+
+    Ok(Some((output, errors)))
+}
+
+/// Return `true` if the file at `path` has an executable permission bit.
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    path.metadata().map(|m| m.permissions().mode() & 0o111 != 0).unwrap_or(false)
+}
+
+/// Return `true` if the file at `path` exists, there is no executable
+/// permission outside of unix.
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Split the output of a script between its header fields and its body.
+///
+/// (RFC 3875 6.2)
+fn parse_output(output: &[u8]) -> Option<(Headers, &[u8])> {
+    let mut headers = Headers::new();
+    let mut start = 0;
+    loop {
+        let end = start + output[start..].iter().position(|&b| b == b'\n')?;
+        let line = String::from_utf8_lossy(&output[start..end]);
+        let line = line.trim_end_matches('\r');
+        start = end + 1;
+        if line.is_empty() {
+            return Some((headers, &output[start..]));
+        }
+
+        let mut fields = line.splitn(2, ':');
+        match (fields.next(), fields.next()) {
+            (Some(name), Some(value)) => headers.set(name.trim(), value.trim()),
+            _                         => return None
+        }
+    }
+}
+
+/// Get the request for the path of a local redirect response of a script,
+/// processed by the server as a new GET request, or `None` if it is not one.
+///
+/// (RFC 3875 6.2.2)
+pub fn local_redirect(req: &Request, headers: &Headers) -> Option<Request> {
+    let location = match headers.get("location") {
+        Some(location) if location.starts_with('/') && !headers.contains_key("status") => location,
+        _ => return None
+    };
+
+    let mut req = req.clone();
+    if req.original_uri.is_none() {
+        req.original_uri = Some(req.uri.clone());
+    }
+    req.method = "GET".into();
+    req.uri = location.clone();
+    req.body.clear();
+    req.headers.remove("content-length");
+    req.headers.remove("content-type");
+
+    Some(req)
+}
+
+/// Set the status and the headers of the response from the header fields
+/// of the output of a script.
+pub fn set_response_head(res: &mut Response, headers: &Headers) {
+    for (name, value) in headers {
+        if name != "status" {
+            res.headers.set(&name, &value);
+        }
+    }
+
+    match headers.get("status") {
+        Some(status) => {
+            let mut fields = status.splitn(2, ' ');
+            let status_code = fields.next().and_then(|code| code.parse().ok()).unwrap_or(500);
+            res.set_status(status_code);
+            if let Some(message) = fields.next() {
+                res.status_message = message.trim().into();
+            }
+        },
+        None if headers.contains_key("location") => res.set_status(302),
+        None                                     => res.set_status(200)
+    }
+}
+
+/// Execute the CGI scripts of `Server::cgi` and send their output.
+pub fn handler(req: Request, mut res: Response) -> Response {
+    if res.is_ended() {
+        return res;
+    }
+
+    let server = res.server.clone();
+    let cgi = match server.cgi {
+        None          => return res,
+        Some(ref cgi) => cgi
+    };
+
+    let path = req.canonicalized_uri();
+    let (script, script_name, path_info) = match cgi.find_script(&path) {
+        Some(script) => script,
+        None if path.starts_with(&cgi.path_prefix) => {
+            res.set_status(404);
+            res.end();
+            return res;
+        },
+        None => return res
+    };
+
+    // The script is executed from its directory, where a path relative to
+    // the working directory of the server would not be found
+    let script = match script.canonicalize() {
+        Ok(script) => script,
+        Err(e)     => {
+            server.log_error(&format!("Could not resolve script {}: {}", script.display(), e));
+            res.set_status(500);
+            res.end();
+            return res;
+        }
+    };
+    if !is_executable(&script) {
+        res.set_status(403);
+        res.end();
+        return res;
+    }
+
+    let vars = environment(&req, &server.root_path, &script, &script_name, &path_info);
+    let (output, errors) = match run(cgi, &script, vars, req.body.clone()) {
+        Ok(Some(output)) => output,
+        Ok(None) => {
+            server.log_error(&format!("Script {} timed out", script.display()));
+            res.set_status(504);
+            res.end();
+            return res;
+        },
+        Err(e) => {
+            server.log_error(&format!("Could not execute script {}: {}", script.display(), e));
+            res.set_status(500);
+            res.end();
+            return res;
+        }
+    };
+    for line in errors.lines() {
+        server.log_error(&format!("{}: {}", script.display(), line));
+    }
+
+    let (headers, body) = match parse_output(&output) {
+        Some(output) => output,
+        None => {
+            server.log_error(&format!("Malformed header from script {}", script.display()));
+            res.set_status(500);
+            res.end();
+            return res;
+        }
+    };
+
+    if let Some(req) = local_redirect(&req, &headers) {
+        res.redirect_request(req);
+        return res;
+    }
+    set_response_head(&mut res, &headers);
+
+    if req.method == "HEAD" {
+        res.headers.set("content-length", &body.len().to_string());
+        res.end();
+    } else {
+        res.send(body);
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use time;
+
+    use http::server::Server;
+
+    #[cfg(unix)]
+    fn script(dir: &Path, name: &str, content: &str) {
+        let path = dir.join(name);
+        fs::write(&path, content).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_script() {
+        let dir = env::temp_dir().join(format!("simpleton-cgi-find-{}", time::precise_time_ns()));
+        fs::create_dir_all(dir.join("tools")).unwrap();
+        script(&dir, "hello", "");
+        script(&dir.join("tools"), "report.pl", "");
+
+        let mut cgi = Cgi::new(dir.to_str().unwrap());
+        let found = cgi.find_script("/cgi-bin/hello/a/b");
+        assert_eq!(found, Some((dir.join("hello"), "/cgi-bin/hello".into(), "/a/b".into())));
+        assert_eq!(cgi.find_script("/cgi-bin/missing"), None);
+        assert_eq!(cgi.find_script("/tools/report.pl"), None);
+
+        cgi.extensions = vec!["pl".into()];
+        let found = cgi.find_script("/tools/report.pl");
+        assert_eq!(found, Some((dir.join("tools/report.pl"), "/tools/report.pl".into(), "".into())));
+        assert_eq!(cgi.find_script("/hello"), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_output() {
+        let (headers, body) = parse_output(b"Status: 404 Not Found\r\nContent-Type: text/plain\r\n\r\nMissing").unwrap();
+        assert_eq!(headers.get("status"), Some(&"404 Not Found".into()));
+        assert_eq!(headers.get("content-type"), Some(&"text/plain".into()));
+        assert_eq!(body, b"Missing");
+
+        assert!(parse_output(b"Content-Type: text/plain").is_none());
+        assert!(parse_output(b"Hello, World!\n\n").is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_handler() {
+        let dir = env::temp_dir().join(format!("simpleton-cgi-{}", time::precise_time_ns()));
+        fs::create_dir_all(&dir).unwrap();
+        script(&dir, "echo", "#!/bin/sh\nprintf 'Content-Type: text/plain\\n\\n'\nprintf '%s %s %s ' \"$REQUEST_METHOD\" \"$QUERY_STRING\" \"$PATH_INFO\"\ncat\n");
+        script(&dir, "redirect", "#!/bin/sh\nprintf 'Location: /index.html\\n\\n'\n");
+        script(&dir, "slow", "#!/bin/sh\nsleep 5\n");
+        script(&dir, "closed", "#!/bin/sh\nprintf 'Content-Type: text/plain\\n\\n'\nexec >&-\nsleep 5\n");
+        script(&dir, "background", "#!/bin/sh\nsleep 5 >/dev/null &\nprintf 'Content-Type: text/plain\\n\\nDone'\n");
+
+        let mut server = Server::new();
+        let mut cgi = Cgi::new(dir.to_str().unwrap());
+        cgi.timeout = Duration::from_millis(200);
+        server.cgi = Some(cgi);
+
+        let mut req = Request::new("POST", "example.com", "/cgi-bin/echo/info?a=1");
+        req.body = b"Hello".to_vec();
+        let res = handler(req, Response::new(server.clone()));
+        assert_eq!(res.status_code, 200);
+        assert_eq!(res.headers.get("content-type"), Some(&"text/plain".into()));
+        assert_eq!(res.body(), b"POST a=1 /info Hello");
+
+        let req = Request::new("GET", "example.com", "/cgi-bin/redirect");
+        let mut res = handler(req, Response::new(server.clone()));
+        assert!(!res.is_ended());
+        assert_eq!(res.take_redirected_request().unwrap().uri, "/index.html");
+
+        let req = Request::new("GET", "example.com", "/cgi-bin/slow");
+        let res = handler(req, Response::new(server.clone()));
+        assert_eq!(res.status_code, 504);
+
+        // The timeout applies to the exit of the script, after its output
+        let req = Request::new("GET", "example.com", "/cgi-bin/closed");
+        let res = handler(req, Response::new(server.clone()));
+        assert_eq!(res.status_code, 504);
+
+        // But not to its standard error kept open by another process
+        let req = Request::new("GET", "example.com", "/cgi-bin/background");
+        let res = handler(req, Response::new(server));
+        assert_eq!(res.status_code, 200);
+        assert_eq!(res.body(), b"Done");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_handler_relative_dir() {
+        // Relative to the working directory of the tests, the crate root
+        let dir = PathBuf::from(format!("target/simpleton-cgi-relative-{}", time::precise_time_ns()));
+        fs::create_dir_all(&dir).unwrap();
+        script(&dir, "hello", "#!/bin/sh\nprintf 'Content-Type: text/plain\\n\\nHello'\n");
+
+        let mut server = Server::new();
+        server.cgi = Some(Cgi::new(dir.to_str().unwrap()));
+
+        let req = Request::new("GET", "example.com", "/cgi-bin/hello");
+        let res = handler(req, Response::new(server));
+        assert_eq!(res.status_code, 200);
+        assert_eq!(res.body(), b"Hello");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,533 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use getrandom;
+use md5;
+use sha2::{Digest, Sha256};
+
+use http::handlers::basic_auth;
+use http::request::Request;
+use http::response::Response;
+
+/// Hash algorithm of the Digest scheme
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Algorithm {
+    Md5,
+    Sha256
+}
+
+impl Algorithm {
+    /// Parse the name of an algorithm, `MD5` when it is absent.
+    ///
+    /// (RFC 7616 3.3)
+    pub fn parse(name: Option<&str>) -> Option<Algorithm> {
+        match name.map(|name| name.to_uppercase()).as_deref() {
+            None | Some("MD5") => Some(Algorithm::Md5),
+            Some("SHA-256")    => Some(Algorithm::Sha256),
+            Some(_)            => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Algorithm::Md5    => "MD5",
+            Algorithm::Sha256 => "SHA-256"
+        }
+    }
+
+    /// Hash `data` and encode it in lowercase hexadecimal.
+    pub fn hash(&self, data: &str) -> String {
+        match *self {
+            Algorithm::Md5    => format!("{:x}", md5::compute(data)),
+            Algorithm::Sha256 => format!("{:x}", Sha256::digest(data))
+        }
+    }
+}
+
+/// Users and hashes of their credentials of an `htdigest` file
+#[derive(Clone, Debug, Default)]
+pub struct Htdigest {
+    entries: Vec<(String, String, String)>
+}
+
+impl Htdigest {
+    /// Parse the lines `user:realm:hash` of an `htdigest` file, where the
+    /// hash is `MD5(user:realm:password)` in hexadecimal, or its SHA-256
+    /// equivalent, ignoring empty lines and comments starting with `#`.
+    pub fn parse(text: &str) -> Result<Htdigest, String> {
+        let mut entries = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.splitn(3, ':').collect();
+            match fields[..] {
+                [user, realm, hash] if hash.len() == 32 || hash.len() == 64 => {
+                    entries.push((user.into(), realm.into(), hash.to_lowercase()));
+                },
+                _ => return Err(format!("Could not parse htdigest entry (line {})", i + 1))
+            }
+        }
+
+        Ok(Htdigest { entries })
+    }
+
+    pub fn from_file(path: &str) -> Result<Htdigest, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Htdigest::parse(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    /// Get the hash of the credentials of `user` in `realm` computed with
+    /// `algorithm`.
+    pub fn hash(&self, user: &str, realm: &str, algorithm: Algorithm) -> Option<&str> {
+        let len = match algorithm {
+            Algorithm::Md5    => 32,
+            Algorithm::Sha256 => 64
+        };
+        self.entries.iter().find(|&(name, space, hash)| {
+            name == user && space == realm && hash.len() == len
+        }).map(|(_, _, hash)| hash.as_str())
+    }
+}
+
+/// Nonce given to a client in a challenge
+struct Nonce {
+    created_at: Instant,
+
+    /// Greatest nonce-count used by the client with the nonce.
+    count: u32
+}
+
+/// Nonces given to the clients, with their order of creation to forget
+/// the oldest ones without going through all of them
+#[derive(Default)]
+struct Nonces {
+    nonces: HashMap<String, Nonce>,
+    order: VecDeque<String>
+}
+
+impl Nonces {
+    /// Add a nonce, forgetting the expired nonces and the oldest ones above
+    /// `max_len`.
+    fn insert(&mut self, value: &str, lifetime: Duration, max_len: usize) {
+        while let Some(oldest) = self.order.front() {
+            let is_expired = self.nonces.get(oldest).is_none_or(|nonce| nonce.created_at.elapsed() >= lifetime);
+            if !is_expired && self.order.len() < max_len {
+                break;
+            }
+            if let Some(oldest) = self.order.pop_front() {
+                self.nonces.remove(&oldest);
+            }
+        }
+
+        self.order.push_back(value.into());
+        self.nonces.insert(value.into(), Nonce { created_at: Instant::now(), count: 0 });
+    }
+}
+
+/// Configuration of the HTTP Digest authentication, with the nonces
+/// shared between the threads of the server
+#[derive(Clone)]
+pub struct DigestAuth {
+    /// Protection space given to the clients in the challenges, which
+    /// must be the realm of the users in the `htdigest` file.
+    pub realm: String,
+
+    /// Path prefixes of the protected requests, like `/private`, matching
+    /// the path itself and the paths below it.
+    pub path_prefixes: Vec<String>,
+
+    /// Duration after which a nonce is stale, and the client must
+    /// authenticate again with a new one.
+    pub nonce_lifetime: Duration,
+
+    /// Maximum number of nonces remembered, the oldest ones are forgotten
+    /// so that the challenges of anonymous clients cannot exhaust memory.
+    pub max_nonces: usize,
+
+    pub users: Htdigest,
+
+    nonces: Arc<Mutex<Nonces>>
+}
+
+impl DigestAuth {
+    /// Create an authentication of the users of `htdigest` in `realm`
+    /// protecting every path.
+    pub fn new(realm: &str, users: Htdigest) -> DigestAuth {
+        DigestAuth {
+            realm: realm.into(),
+            path_prefixes: vec!["/".into()],
+            nonce_lifetime: Duration::from_secs(300),
+            max_nonces: 10_000,
+            users,
+            nonces: Arc::new(Mutex::new(Nonces::default()))
+        }
+    }
+
+    /// Create a nonce, and forget the expired or oldest ones.
+    fn create_nonce(&self) -> String {
+        let mut bytes = [0; 16];
+        getrandom::fill(&mut bytes).expect("Could not generate a nonce");
+        let nonce = bytes.iter().fold(String::new(), |mut s, byte| {
+            let _ = write!(s, "{:02x}", byte);
+            s
+        });
+
+        self.nonces.lock().unwrap().insert(&nonce, self.nonce_lifetime, self.max_nonces);
+
+        nonce
+    }
+
+    /// Build the challenges of the response, one for each algorithm in order
+    /// of preference.
+    ///
+    /// (RFC 7616 3.3)
+    pub fn challenges(&self, stale: bool) -> String {
+        let nonce = self.create_nonce();
+        let realm = self.realm.replace('\\', "\\\\").replace('"', "\\\"");
+
+        [Algorithm::Sha256, Algorithm::Md5].iter().map(|algorithm| {
+            let mut challenge = format!(
+                "Digest realm=\"{}\", qop=\"auth\", algorithm={}, nonce=\"{}\"",
+                realm, algorithm.name(), nonce
+            );
+            if stale {
+                challenge.push_str(", stale=true");
+            }
+            challenge
+        }).collect::<Vec<_>>().join(", ")
+    }
+
+    /// Verify the parameters of the credentials of `req`, and return the
+    /// authenticated user, or an error indicating if the credentials are
+    /// valid but the nonce is stale.
+    ///
+    /// (RFC 7616 3.4)
+    fn verify(&self, req: &Request, params: &HashMap<String, String>) -> Result<String, bool> {
+        let param = |name: &str| params.get(name).map(|value| value.as_str()).ok_or(false);
+        let user = param("username")?;
+        let nonce = param("nonce")?;
+        let nc = param("nc")?;
+        let cnonce = param("cnonce")?;
+        let response = param("response")?.to_lowercase();
+        if param("realm")? != self.realm || param("qop")? != "auth" {
+            return Err(false);
+        }
+
+        // The URI must be the Request-URI received from the client
+        let uri = param("uri")?;
+        if uri != req.original_uri.as_ref().unwrap_or(&req.uri) {
+            return Err(false);
+        }
+
+        let algorithm = Algorithm::parse(params.get("algorithm").map(|name| name.as_str())).ok_or(false)?;
+        let ha1 = self.users.hash(user, &self.realm, algorithm).ok_or(false)?;
+        let ha2 = algorithm.hash(&format!("{}:{}", req.method, uri));
+        let expected = algorithm.hash(&format!("{}:{}:{}:{}:auth:{}", ha1, nonce, nc, cnonce, ha2));
+        if !basic_auth::constant_time_eq(expected.as_bytes(), response.as_bytes()) {
+            return Err(false);
+        }
+
+        // The nonce-count must increase with each request to prevent
+        // replay attacks.
+        let count = u32::from_str_radix(nc, 16).map_err(|_| false)?;
+        let mut nonces = self.nonces.lock().unwrap();
+        match nonces.nonces.get_mut(nonce) {
+            Some(nonce) if nonce.created_at.elapsed() >= self.nonce_lifetime => Err(true),
+            Some(nonce) if count > nonce.count => {
+                nonce.count = count;
+                Ok(user.into())
+            },
+            Some(_) => Err(false),
+            None    => Err(true)
+        }
+    }
+}
+
+/// Parse the parameters of the value of an `Authorization` header with
+/// the `Digest` scheme, like `username="Mufasa", nc=00000001`.
+pub fn parse_params(value: &str) -> Option<HashMap<String, String>> {
+    let (scheme, rest) = value.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("digest") {
+        return None;
+    }
+
+    let mut params = HashMap::new();
+    let mut chars = rest.chars().peekable();
+    loop {
+        while chars.peek().is_some_and(|&c| c == ',' || c == ' ') {
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut name = String::new();
+        for c in chars.by_ref() {
+            if c == '=' {
+                break;
+            }
+            name.push(c);
+        }
+
+        // Quoted string with escaped characters, or token
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next()? {
+                    '"'  => break,
+                    '\\' => value.push(chars.next()?),
+                    c    => value.push(c)
+                }
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c == ',' {
+                    break;
+                }
+                value.push(c);
+                chars.next();
+            }
+        }
+
+        params.insert(name.trim().to_lowercase(), value.trim().to_string());
+    }
+
+    Some(params)
+}
+
+/// Require the credentials of a user of `Server::digest_auth` for the
+/// requests for which the server requires the Digest scheme, and give the
+/// authenticated user to the following handlers. Credentials of the Basic
+/// scheme are left to the `basic_auth` handler when it is also required.
+pub fn handler(req: Request, mut res: Response) -> Response {
+    if res.is_ended() {
+        return res;
+    }
+
+    let server = res.server.clone();
+    let auth = match server.digest_auth {
+        None           => return res,
+        Some(ref auth) => auth
+    };
+    // Applications behind a proxy or a gateway decode the path
+    let path = req.decoded_path();
+    let schemes = basic_auth::required_schemes(&server, &path);
+    if !schemes.contains(&"digest") {
+        return res;
+    }
+
+    // Skip the users already authenticated with this scheme, like before
+    // an internal redirect, whose nonce-count cannot be used again, and the
+    // Basic credentials left to the `basic_auth` handler.
+    let scheme = basic_auth::authorization_scheme(&req);
+    let is_basic = scheme.as_deref() == Some("basic") && schemes.contains(&"basic");
+    if req.auth_scheme.as_deref() == Some("digest") || is_basic {
+        return res;
+    }
+
+    let params = req.headers.get("authorization").and_then(|value| parse_params(value));
+    let stale = match params.map(|params| auth.verify(&req, &params)) {
+        Some(Ok(user)) => {
+            let mut req = req.clone();
+            req.user = Some(user);
+            req.auth_scheme = Some("digest".into());
+            res.update_request(req);
+            return res;
+        },
+        Some(Err(stale)) => stale,
+        None             => false
+    };
+
+    basic_auth::unauthorized(&mut res, &path, stale);
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use http::server::Server;
+
+    const REALM: &str = "http-auth@example.org";
+    const NONCE: &str = "7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v";
+
+    /// Server with the user of the example of RFC 7616 3.9.1.
+    fn server() -> Server {
+        let text = format!(
+            "Mufasa:{}:{}\nMufasa:{}:{}\n",
+            REALM, Algorithm::Md5.hash(&format!("Mufasa:{}:Circle of Life", REALM)),
+            REALM, Algorithm::Sha256.hash(&format!("Mufasa:{}:Circle of Life", REALM))
+        );
+        let auth = DigestAuth::new(REALM, Htdigest::parse(&text).unwrap());
+        auth.nonces.lock().unwrap().insert(NONCE, auth.nonce_lifetime, auth.max_nonces);
+
+        let mut server = Server::new();
+        server.digest_auth = Some(auth);
+        server
+    }
+
+    fn authorization(algorithm: &str, nc: &str, response: &str) -> String {
+        format!(
+            "Digest username=\"Mufasa\", realm=\"{}\", uri=\"/dir/index.html\", algorithm={}, nonce=\"{}\", nc={}, cnonce=\"f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ\", qop=auth, response=\"{}\", opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"",
+            REALM, algorithm, NONCE, nc, response
+        )
+    }
+
+    #[test]
+    fn test_nonces() {
+        let mut nonces = Nonces::default();
+        nonces.insert("a", Duration::from_secs(60), 2);
+        nonces.insert("b", Duration::from_secs(60), 2);
+        nonces.insert("c", Duration::from_secs(60), 2);
+        assert!(!nonces.nonces.contains_key("a"));
+        assert!(nonces.nonces.contains_key("b") && nonces.nonces.contains_key("c"));
+
+        nonces.insert("d", Duration::from_secs(0), 2);
+        assert_eq!(nonces.order, vec!["d".to_string()]);
+        assert_eq!(nonces.nonces.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_params() {
+        let params = parse_params("Digest username=\"Mu\\\"fasa\", nc=00000001,qop=auth, uri=\"/a,b\"").unwrap();
+        assert_eq!(params["username"], "Mu\"fasa");
+        assert_eq!(params["nc"], "00000001");
+        assert_eq!(params["qop"], "auth");
+        assert_eq!(params["uri"], "/a,b");
+
+        assert!(parse_params("Basic YWxpY2U6c2VjcmV0").is_none());
+        assert!(parse_params("Digest username=\"Mufasa").is_none());
+    }
+
+    #[test]
+    fn test_handler() {
+        let server = server();
+
+        let req = Request::new("GET", "example.org", "/dir/index.html");
+        let res = handler(req, Response::new(server.clone()));
+        assert_eq!(res.status_code, 401);
+        let challenges = res.headers.get("www-authenticate").unwrap();
+        assert!(challenges.starts_with("Digest realm=\"http-auth@example.org\", qop=\"auth\", algorithm=SHA-256, nonce=\""));
+        assert!(challenges.contains(", Digest realm=\"http-auth@example.org\", qop=\"auth\", algorithm=MD5, nonce=\""));
+
+        // Responses of the examples of RFC 7616 3.9.1
+        let mut req = Request::new("GET", "example.org", "/dir/index.html");
+        req.headers.set("authorization", &authorization("MD5", "00000001", "8ca523f5e9506fed4657c9700eebdbec"));
+        let mut res = handler(req.clone(), Response::new(server.clone()));
+        assert!(!res.is_ended());
+        assert_eq!(res.take_updated_request().unwrap().user, Some("Mufasa".into()));
+
+        // The same nonce-count cannot be used again
+        let res = handler(req, Response::new(server.clone()));
+        assert_eq!(res.status_code, 401);
+        assert!(!res.headers.get("www-authenticate").unwrap().contains("stale=true"));
+
+        // Valid credentials with an expired nonce
+        let ha1 = Algorithm::Md5.hash(&format!("Mufasa:{}:Circle of Life", REALM));
+        let ha2 = Algorithm::Md5.hash("GET:/dir/index.html");
+        let cnonce = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+        let response = Algorithm::Md5.hash(&format!("{}:{}:00000002:{}:auth:{}", ha1, NONCE, cnonce, ha2));
+        let mut server = server;
+        server.digest_auth.as_mut().unwrap().nonce_lifetime = Duration::from_secs(0);
+        let mut req = Request::new("GET", "example.org", "/dir/index.html");
+        req.headers.set("authorization", &authorization("MD5", "00000002", &response));
+        let res = handler(req, Response::new(server));
+        assert_eq!(res.status_code, 401);
+        assert!(res.headers.get("www-authenticate").unwrap().contains("stale=true"));
+    }
+
+    #[test]
+    fn test_handler_with_basic_auth() {
+        let mut server = server();
+        let users = basic_auth::Htpasswd::parse("alice:{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=").unwrap();
+        server.basic_auth = Some(basic_auth::BasicAuth::new(users));
+        let chain = |req: Request| {
+            let mut res = basic_auth::handler(req.clone(), Response::new(server.clone()));
+            let req = res.take_updated_request().unwrap_or(req);
+            let mut res = handler(req.clone(), res);
+            let req = res.take_updated_request().unwrap_or(req);
+            (req, res)
+        };
+
+        // A single response gives both challenges
+        let (_, res) = chain(Request::new("GET", "example.org", "/dir/index.html"));
+        assert_eq!(res.status_code, 401);
+        let challenges = res.headers.get("www-authenticate").unwrap();
+        assert!(challenges.starts_with("Digest realm=\"http-auth@example.org\""));
+        assert!(challenges.ends_with(", Basic realm=\"Restricted\", charset=\"UTF-8\""));
+
+        let mut req = Request::new("GET", "example.org", "/dir/index.html");
+        req.headers.set("authorization", &authorization("MD5", "00000001", "8ca523f5e9506fed4657c9700eebdbec"));
+        let (req, res) = chain(req);
+        assert!(!res.is_ended());
+        assert_eq!(req.user, Some("Mufasa".into()));
+
+        let mut req = Request::new("GET", "example.org", "/dir/index.html");
+        req.headers.set("authorization", "Basic YWxpY2U6c2VjcmV0");
+        let (req, res) = chain(req);
+        assert!(!res.is_ended());
+        assert_eq!(req.user, Some("alice".into()));
+
+        let mut req = Request::new("GET", "example.org", "/dir/index.html");
+        req.headers.set("authorization", "Bearer secret");
+        let (_, res) = chain(req);
+        assert_eq!(res.status_code, 401);
+    }
+
+    #[test]
+    fn test_handler_with_overlapping_basic_auth() {
+        let mut server = server();
+        server.digest_auth.as_mut().unwrap().path_prefixes = vec!["/dir".into()];
+        let users = basic_auth::Htpasswd::parse("alice:{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=").unwrap();
+        server.basic_auth = Some(basic_auth::BasicAuth::new(users));
+        let chain = |req: Request| {
+            let mut res = basic_auth::handler(req.clone(), Response::new(server.clone()));
+            let req = res.take_updated_request().unwrap_or(req);
+            let mut res = handler(req.clone(), res);
+            let req = res.take_updated_request().unwrap_or(req);
+            (req, res)
+        };
+
+        // The Basic credentials do not open the area of the Digest scheme
+        let mut req = Request::new("GET", "example.org", "/dir/index.html");
+        req.headers.set("authorization", "Basic YWxpY2U6c2VjcmV0");
+        let (_, res) = chain(req);
+        assert_eq!(res.status_code, 401);
+        assert!(!res.headers.get("www-authenticate").unwrap().contains("Basic"));
+
+        let mut req = Request::new("GET", "example.org", "/dir/index.html");
+        req.headers.set("authorization", &authorization("MD5", "00000001", "8ca523f5e9506fed4657c9700eebdbec"));
+        let (req, res) = chain(req);
+        assert!(!res.is_ended());
+        assert_eq!(req.auth_scheme, Some("digest".into()));
+
+        // Nor the Digest ones the area of the Basic scheme
+        let mut req = Request::new("GET", "example.org", "/index.html");
+        req.headers.set("authorization", &authorization("MD5", "00000002", "8ca523f5e9506fed4657c9700eebdbec"));
+        let (_, res) = chain(req);
+        assert_eq!(res.status_code, 401);
+        assert_eq!(res.headers.get("www-authenticate"), Some(&"Basic realm=\"Restricted\", charset=\"UTF-8\"".into()));
+
+        let mut req = Request::new("GET", "example.org", "/index.html");
+        req.headers.set("authorization", "Basic YWxpY2U6c2VjcmV0");
+        let (req, res) = chain(req);
+        assert!(!res.is_ended());
+        assert_eq!(req.user, Some("alice".into()));
+    }
+
+    #[test]
+    fn test_handler_sha256() {
+        let mut req = Request::new("GET", "example.org", "/dir/index.html");
+        let response = "753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1";
+        req.headers.set("authorization", &authorization("SHA-256", "00000001", response));
+        let mut res = handler(req, Response::new(server()));
+        assert!(!res.is_ended());
+        assert_eq!(res.take_updated_request().unwrap().user, Some("Mufasa".into()));
+    }
+}
@@ -0,0 +1,237 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::PathBuf;
+
+use http::escape;
+use http::headers;
+use http::request::Request;
+use http::response::Response;
+use http::server::Handler;
+
+/// Media types that can be used for the body of an error response,
+/// in order of preference when the client has none.
+const MEDIA_TYPES: [&str; 2] = ["text/html", "application/problem+json"];
+
+/// Give a body to an error response (status code `4xx` or `5xx`) left
+/// empty by the previous handlers.
+///
+/// The body is produced, in order of preference, by an error handler
+/// registered for the status code with `Server::add_error_handler`, by a
+/// static error page found in `Server::error_pages_path`, or by a default
+/// HTML page or JSON problem document (RFC 9457) chosen by the `Accept`
+/// header of the request.
+///
+/// This handler is called by the server before writing the response, it
+/// does not need to be added to the list of handlers.
+pub fn handler(req: Request, mut res: Response) -> Response {
+    if res.status_code < 400 || res.is_sent() || !res.body().is_empty() || res.is_streamed() {
+        return res;
+    }
+
+    // The server MUST NOT return a message-body in the response
+    // of a HEAD request.
+    if req.method == "HEAD" {
+        return res;
+    }
+
+    let server = res.server.clone();
+
+    if let Some(f) = find_error_handler(&server.error_handlers, res.status_code) {
+        return f(req, res);
+    }
+
+    let accept = req.headers.get("accept").cloned().unwrap_or_default();
+    if negotiate(&accept) == "application/problem+json" {
+        let body = problem_document(&req, &res);
+        res.headers.set("content-type", "application/problem+json");
+        res.send(body.as_bytes());
+        return res;
+    }
+
+    let mut body = vec![];
+    if let Some(ref path) = server.error_pages_path {
+        read_error_page(path, res.status_code, &mut body);
+    }
+    if body.is_empty() {
+        body = html_document(&res).into_bytes();
+    }
+    res.headers.set("content-type", "text/html");
+    res.send(&body);
+
+    res
+}
+
+/// Find the error handler registered for the narrowest range of status
+/// codes containing `code`.
+fn find_error_handler(error_handlers: &[(u16, u16, Handler)], code: u16) -> Option<Handler> {
+    error_handlers.iter()
+        .filter(|&&(first, last, _)| first <= code && code <= last)
+        .min_by_key(|&&(first, last, _)| last - first)
+        .map(|&(_, _, f)| f)
+}
+
+/// Choose the media type of the error body preferred by the client.
+fn negotiate(accept: &str) -> &'static str {
+    let ranges = headers::quality_values(accept);
+    if ranges.is_empty() {
+        return MEDIA_TYPES[0];
+    }
+
+    let mut best = (MEDIA_TYPES[0], 0.0);
+    for &media_type in &MEDIA_TYPES {
+        let q = quality(&ranges, media_type);
+        if q > best.1 {
+            best = (media_type, q);
+        }
+    }
+
+    best.0
+}
+
+/// Get the quality value of the most specific media range matching
+/// `media_type`.
+fn quality(ranges: &[(String, f32)], media_type: &str) -> f32 {
+    let main_type = media_type.split('/').next().unwrap();
+
+    let mut best = (0, 0.0);
+    for &(ref range, q) in ranges {
+        let specificity = if range == media_type {
+            3
+        } else if range == "application/json" && media_type.ends_with("+json") {
+            2
+        } else if *range == format!("{}/*", main_type) {
+            1
+        } else if range == "*/*" {
+            0
+        } else {
+            continue;
+        };
+        if specificity >= best.0 {
+            best = (specificity, q);
+        }
+    }
+
+    best.1
+}
+
+/// Read `<code>.html`, or `<class>xx.html` (for example `4xx.html`),
+/// from the directory at `path`.
+fn read_error_page(path: &str, code: u16, buf: &mut Vec<u8>) {
+    let names = vec![
+        format!("{}.html", code),
+        format!("{}xx.html", code / 100)
+    ];
+
+    for name in names {
+        if let Ok(mut file) = File::open(PathBuf::from(path).join(name)) {
+            if file.read_to_end(buf).is_ok() {
+                return;
+            }
+            buf.clear();
+        }
+    }
+}
+
+fn html_document(res: &Response) -> String {
+    let title = escape::html(&format!("{} {}", res.status_code, res.status_message));
+
+    format!(
+        "<!DOCTYPE html>\n\
+         <html>\n\
+         <head><title>{}</title></head>\n\
+         <body><h1>{}</h1></body>\n\
+         </html>\n",
+        title,
+        title
+    )
+}
+
+fn problem_document(req: &Request, res: &Response) -> String {
+    format!(
+        "{{\"type\":\"about:blank\",\"title\":\"{}\",\"status\":{},\"instance\":\"{}\"}}\n",
+        escape::json(&res.status_message),
+        res.status_code,
+        escape::json(&req.uri)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use http::server::Server;
+
+    fn teapot_handler(_req: Request, mut res: Response) -> Response {
+        res.send("I'm a teapot".as_bytes());
+
+        res
+    }
+
+    fn not_found() -> Response {
+        let mut res = Response::new(Server::new());
+        res.status_code = 404;
+        res.status_message = "Not Found".into();
+        res.end();
+
+        res
+    }
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate(""), "text/html");
+        assert_eq!(negotiate("*/*"), "text/html");
+        assert_eq!(negotiate("application/json"), "application/problem+json");
+        assert_eq!(negotiate("text/html, application/json"), "text/html");
+        assert_eq!(negotiate("text/*;q=0.5, application/*"), "application/problem+json");
+        assert_eq!(negotiate("image/png"), "text/html");
+    }
+
+    #[test]
+    fn test_find_error_handler() {
+        let error_handlers: Vec<(u16, u16, Handler)> = vec![
+            (400, 599, handler),
+            (418, 418, teapot_handler)
+        ];
+
+        assert!(find_error_handler(&error_handlers, 200).is_none());
+
+        let req = Request::new("GET", "example.com", "/missing");
+        let f = find_error_handler(&error_handlers, 404).unwrap();
+        assert!(f(req, not_found()).body().starts_with(b"<!DOCTYPE html>"));
+
+        let req = Request::new("GET", "example.com", "/missing");
+        let f = find_error_handler(&error_handlers, 418).unwrap();
+        assert_eq!(f(req, not_found()).body(), "I'm a teapot".as_bytes());
+    }
+
+    #[test]
+    fn test_handler() {
+        let req = Request::new("GET", "example.com", "/missing");
+        let res = handler(req, not_found());
+        assert_eq!(res.headers.get("content-type"), Some(&"text/html".into()));
+        assert!(String::from_utf8_lossy(res.body()).contains("<h1>404 Not Found</h1>"));
+
+        let mut req = Request::new("GET", "example.com", "/missing");
+        req.headers.set("accept", "application/json");
+        let res = handler(req, not_found());
+        assert_eq!(res.headers.get("content-type"), Some(&"application/problem+json".into()));
+        assert!(String::from_utf8_lossy(res.body()).contains("\"status\":404"));
+
+        let req = Request::new("HEAD", "example.com", "/missing");
+        let res = handler(req, not_found());
+        assert!(res.body().is_empty());
+    }
+
+    #[test]
+    fn test_handler_with_error_handler() {
+        let mut server = Server::new();
+        server.add_error_handler(404, teapot_handler);
+
+        let mut res = not_found();
+        res.server = server;
+
+        let req = Request::new("GET", "example.com", "/missing");
+        let res = handler(req, res);
+        assert_eq!(res.body(), "I'm a teapot".as_bytes());
+    }
+}
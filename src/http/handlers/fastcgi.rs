@@ -0,0 +1,694 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::BufReader;
+use std::io::prelude::*;
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use http::handlers::cgi;
+use http::headers::Headers;
+use http::request::Request;
+use http::response::Response;
+use http::server::Server;
+
+// Types of records
+const BEGIN_REQUEST: u8 = 1;
+const ABORT_REQUEST: u8 = 2;
+const END_REQUEST: u8 = 3;
+const PARAMS: u8 = 4;
+const STDIN: u8 = 5;
+const STDOUT: u8 = 6;
+const STDERR: u8 = 7;
+
+/// Role of the application handling the requests of the server.
+const RESPONDER: u16 = 1;
+
+/// Flag of a `BEGIN_REQUEST` record keeping the connection open after
+/// the request.
+const KEEP_CONN: u8 = 1;
+
+/// Maximum length of the content of a record.
+const MAX_CONTENT_LENGTH: usize = 65535;
+
+/// Record of the FastCGI protocol
+#[derive(Debug, PartialEq)]
+struct Record {
+    kind: u8,
+    request_id: u16,
+    content: Vec<u8>
+}
+
+/// Write a record with `content` no longer than `MAX_CONTENT_LENGTH`.
+fn write_record<W: Write>(writer: &mut W, kind: u8, request_id: u16, content: &[u8]) -> io::Result<()> {
+    // Records are padded to a multiple of 8 bytes
+    let padding = (8 - content.len() % 8) % 8;
+
+    let mut record = Vec::with_capacity(8 + content.len() + padding);
+    record.extend_from_slice(&[1, kind]);
+    record.extend_from_slice(&request_id.to_be_bytes());
+    record.extend_from_slice(&(content.len() as u16).to_be_bytes());
+    record.extend_from_slice(&[padding as u8, 0]);
+    record.extend_from_slice(content);
+    record.extend(vec![0; padding]);
+    writer.write_all(&record)
+}
+
+/// Write `data` as a stream of records, ended by an empty record.
+fn write_stream<W: Write>(writer: &mut W, kind: u8, request_id: u16, data: &[u8]) -> io::Result<()> {
+    for chunk in data.chunks(MAX_CONTENT_LENGTH) {
+        write_record(writer, kind, request_id, chunk)?;
+    }
+    write_record(writer, kind, request_id, &[])
+}
+
+fn read_record<R: Read>(reader: &mut R) -> io::Result<Record> {
+    let mut header = [0; 8];
+    reader.read_exact(&mut header)?;
+    let request_id = u16::from_be_bytes([header[2], header[3]]);
+    let content_length = u16::from_be_bytes([header[4], header[5]]) as usize;
+    let padding_length = header[6] as usize;
+
+    let mut content = vec![0; content_length + padding_length];
+    reader.read_exact(&mut content)?;
+    content.truncate(content_length);
+
+    Ok(Record { kind: header[1], request_id, content })
+}
+
+/// Encode name-value pairs, with lengths of 1 byte up to 127 or else
+/// of 4 bytes with the high bit set.
+fn encode_params(params: &[(String, String)]) -> Vec<u8> {
+    let mut data = vec![];
+    let encode_length = |data: &mut Vec<u8>, length: usize| {
+        if length < 128 {
+            data.push(length as u8);
+        } else {
+            data.extend_from_slice(&(length as u32 | 0x8000_0000).to_be_bytes());
+        }
+    };
+    for (name, value) in params {
+        encode_length(&mut data, name.len());
+        encode_length(&mut data, value.len());
+        data.extend_from_slice(name.as_bytes());
+        data.extend_from_slice(value.as_bytes());
+    }
+
+    data
+}
+
+/// Socket connected to a FastCGI application
+enum Socket {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream)
+}
+
+impl Socket {
+    /// Connect to the application at `address`, like `127.0.0.1:9000`
+    /// or `unix:/run/php/php-fpm.sock`, with writes failing after `timeout`
+    /// so that a stalled application cannot block a request forever.
+    fn connect(address: &str, timeout: Duration) -> io::Result<Socket> {
+        let socket = match address.strip_prefix("unix:") {
+            Some(path) => Socket::connect_unix(path)?,
+            None       => {
+                let addr = match address.to_socket_addrs()?.next() {
+                    Some(addr) => addr,
+                    None       => return Err(io::Error::other("Could not resolve address"))
+                };
+                TcpStream::connect_timeout(&addr, timeout).map(Socket::Tcp)?
+            }
+        };
+        socket.set_write_timeout(timeout)?;
+
+        Ok(socket)
+    }
+
+    #[cfg(unix)]
+    fn connect_unix(path: &str) -> io::Result<Socket> {
+        UnixStream::connect(path).map(Socket::Unix)
+    }
+
+    #[cfg(not(unix))]
+    fn connect_unix(_path: &str) -> io::Result<Socket> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "Unix sockets are not supported"))
+    }
+
+    fn set_write_timeout(&self, timeout: Duration) -> io::Result<()> {
+        match *self {
+            Socket::Tcp(ref stream)  => stream.set_write_timeout(Some(timeout)),
+            #[cfg(unix)]
+            Socket::Unix(ref stream) => stream.set_write_timeout(Some(timeout))
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Socket> {
+        match *self {
+            Socket::Tcp(ref stream)  => stream.try_clone().map(Socket::Tcp),
+            #[cfg(unix)]
+            Socket::Unix(ref stream) => stream.try_clone().map(Socket::Unix)
+        }
+    }
+
+    fn shutdown(&self) {
+        let _ = match *self {
+            Socket::Tcp(ref stream)  => stream.shutdown(Shutdown::Both),
+            #[cfg(unix)]
+            Socket::Unix(ref stream) => stream.shutdown(Shutdown::Both)
+        };
+    }
+}
+
+impl Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Socket::Tcp(ref mut stream)  => stream.read(buf),
+            #[cfg(unix)]
+            Socket::Unix(ref mut stream) => stream.read(buf)
+        }
+    }
+}
+
+impl Write for Socket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Socket::Tcp(ref mut stream)  => stream.write(buf),
+            #[cfg(unix)]
+            Socket::Unix(ref mut stream) => stream.write(buf)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Socket::Tcp(ref mut stream)  => stream.flush(),
+            #[cfg(unix)]
+            Socket::Unix(ref mut stream) => stream.flush()
+        }
+    }
+}
+
+/// Connection to a FastCGI application, whose records are dispatched
+/// to the requests by their ids
+struct Connection {
+    writer: Mutex<Socket>,
+    requests: Mutex<HashMap<u16, Sender<Record>>>,
+    next_id: Mutex<u16>,
+    alive: AtomicBool,
+
+    /// Boolean indicating if the connection is shared between requests.
+    keep_conn: bool
+}
+
+impl Connection {
+    fn open(address: &str, timeout: Duration, keep_conn: bool) -> io::Result<Arc<Connection>> {
+        let socket = Socket::connect(address, timeout)?;
+        let mut reader = socket.try_clone()?;
+        let connection = Arc::new(Connection {
+            writer: Mutex::new(socket),
+            requests: Mutex::new(HashMap::new()),
+            next_id: Mutex::new(0),
+            alive: AtomicBool::new(true),
+            keep_conn
+        });
+
+        let demultiplexer = connection.clone();
+        thread::spawn(move || {
+            while let Ok(record) = read_record(&mut reader) {
+                let requests = demultiplexer.requests.lock().unwrap();
+                if let Some(tx) = requests.get(&record.request_id) {
+                    let _ = tx.send(record);
+                }
+            }
+
+            // The requests still waiting for records are ended
+            demultiplexer.alive.store(false, Ordering::SeqCst);
+            demultiplexer.requests.lock().unwrap().clear();
+        });
+
+        Ok(connection)
+    }
+
+    fn is_alive(&self) -> bool {
+        self.alive.load(Ordering::SeqCst)
+    }
+
+    /// Send a request with its `params` and its `body`, and get the receiver
+    /// of the records of its response, or an error if every request id of
+    /// the connection is in use.
+    fn begin(&self, params: &[(String, String)], body: &[u8]) -> io::Result<(u16, Receiver<Record>)> {
+        let (tx, rx) = mpsc::channel();
+        let request_id = {
+            let mut requests = self.requests.lock().unwrap();
+            if requests.len() >= u16::MAX as usize {
+                return Err(io::Error::new(io::ErrorKind::ResourceBusy, "No request id available"));
+            }
+            let mut next_id = self.next_id.lock().unwrap();
+            loop {
+                *next_id = next_id.checked_add(1).unwrap_or(1);
+                if !requests.contains_key(&next_id) {
+                    break;
+                }
+            }
+            requests.insert(*next_id, tx);
+            *next_id
+        };
+
+        let flags = if self.keep_conn { KEEP_CONN } else { 0 };
+        let mut begin_request = RESPONDER.to_be_bytes().to_vec();
+        begin_request.extend_from_slice(&[flags, 0, 0, 0, 0, 0]);
+
+        let mut writer = self.writer.lock().unwrap();
+        let sent = write_record(&mut *writer, BEGIN_REQUEST, request_id, &begin_request)
+            .and_then(|_| write_stream(&mut *writer, PARAMS, request_id, &encode_params(params)))
+            .and_then(|_| write_stream(&mut *writer, STDIN, request_id, body))
+            .and_then(|_| writer.flush());
+        if let Err(e) = sent {
+            self.alive.store(false, Ordering::SeqCst);
+            self.requests.lock().unwrap().remove(&request_id);
+            return Err(e);
+        }
+
+        Ok((request_id, rx))
+    }
+
+    /// Release the id of a request, aborting it if its response has not
+    /// been completely received.
+    fn end(&self, request_id: u16, completed: bool) {
+        self.requests.lock().unwrap().remove(&request_id);
+        if !self.keep_conn {
+            self.writer.lock().unwrap().shutdown();
+        } else if !completed {
+            let _ = write_record(&mut *self.writer.lock().unwrap(), ABORT_REQUEST, request_id, &[]);
+        }
+    }
+}
+
+/// Reader of the standard output of a request, logging its standard error
+struct ResponseReader {
+    connection: Arc<Connection>,
+    request_id: u16,
+    records: Receiver<Record>,
+    buffer: Vec<u8>,
+    pos: usize,
+    completed: bool,
+    timeout: Duration,
+    server: Server
+}
+
+impl Read for ResponseReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos >= self.buffer.len() && !self.completed {
+            let record = match self.records.recv_timeout(self.timeout) {
+                Ok(record)                          => record,
+                Err(RecvTimeoutError::Timeout)      => return Err(io::ErrorKind::TimedOut.into()),
+                Err(RecvTimeoutError::Disconnected) => return Err(io::ErrorKind::UnexpectedEof.into())
+            };
+            match record.kind {
+                STDOUT => {
+                    self.buffer = record.content;
+                    self.pos = 0;
+                },
+                STDERR => {
+                    for line in String::from_utf8_lossy(&record.content).lines() {
+                        self.server.log_error(&format!("FastCGI: {}", line));
+                    }
+                },
+                END_REQUEST => {
+                    self.completed = true;
+                },
+                _ => {}
+            }
+        }
+
+        let n = (&self.buffer[self.pos..]).read(buf)?;
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl Drop for ResponseReader {
+    fn drop(&mut self) {
+        self.connection.end(self.request_id, self.completed);
+    }
+}
+
+/// Configuration of the FastCGI application handling requests
+#[derive(Clone)]
+pub struct FastCgi {
+    /// Address of the application, like `127.0.0.1:9000` or
+    /// `unix:/run/php/php-fpm.sock`.
+    pub address: String,
+
+    /// Extensions of the scripts handled by the application, like `php`.
+    pub extensions: Vec<String>,
+
+    /// Prefix of the paths of the requests all handled by the application,
+    /// like `/app/`.
+    pub path_prefix: Option<String>,
+
+    /// Document root of the application, the root of the server by default.
+    pub root_path: Option<String>,
+
+    /// Send the requests concurrently over a single connection, if the
+    /// application supports it, instead of a connection per request.
+    pub multiplex: bool,
+
+    /// Timeout of the connection and of the records of the responses.
+    pub timeout: Duration,
+
+    connection: Arc<Mutex<Option<Arc<Connection>>>>
+}
+
+impl FastCgi {
+    pub fn new(address: &str) -> FastCgi {
+        FastCgi {
+            address: address.into(),
+            extensions: vec!["php".into()],
+            path_prefix: None,
+            root_path: None,
+            multiplex: false,
+            timeout: Duration::from_secs(60),
+            connection: Arc::new(Mutex::new(None))
+        }
+    }
+
+    /// Get the `SCRIPT_NAME` and the `PATH_INFO` of the request at `path`,
+    /// or `None` if it is not handled by the application.
+    fn split_script(&self, path: &str) -> Option<(String, String)> {
+        if let Some(ref prefix) = self.path_prefix {
+            if path.starts_with(prefix.as_str()) || path == prefix.trim_end_matches('/') {
+                return Some((path.into(), String::new()));
+            }
+        }
+
+        let mut end = 0;
+        for segment in path.split('/').skip(1) {
+            end += segment.len() + 1;
+            let is_script = segment.rsplit_once('.').is_some_and(|(_, ext)| {
+                self.extensions.iter().any(|extension| extension == ext)
+            });
+            if is_script {
+                return Some((path[..end].into(), path[end..].into()));
+            }
+        }
+
+        None
+    }
+
+    /// Get a connection to the application, shared between requests
+    /// when they are multiplexed.
+    fn connection(&self) -> io::Result<Arc<Connection>> {
+        if !self.multiplex {
+            return Connection::open(&self.address, self.timeout, false);
+        }
+
+        let mut shared = self.connection.lock().unwrap();
+        if let Some(ref connection) = *shared {
+            if connection.is_alive() {
+                return Ok(connection.clone());
+            }
+        }
+        let connection = Connection::open(&self.address, self.timeout, true)?;
+        *shared = Some(connection.clone());
+        Ok(connection)
+    }
+}
+
+/// Read the header fields at the beginning of the output of a script.
+fn read_headers<R: BufRead>(reader: &mut R) -> io::Result<Headers> {
+    let mut headers = Headers::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Malformed header"));
+        }
+        let line = line.trim_end_matches(&['\r', '\n'][..]);
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        match line.split_once(':') {
+            Some((name, value)) => headers.set(name.trim(), value.trim()),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData, "Malformed header"))
+        }
+    }
+}
+
+/// Forward the requests for the scripts of `Server::fastcgi` to its
+/// application and stream their output.
+pub fn handler(req: Request, mut res: Response) -> Response {
+    if res.is_ended() {
+        return res;
+    }
+
+    let server = res.server.clone();
+    let fastcgi = match server.fastcgi {
+        None              => return res,
+        Some(ref fastcgi) => fastcgi
+    };
+
+    let path = req.canonicalized_uri();
+    let (script_name, path_info) = match fastcgi.split_script(&path) {
+        Some(script) => script,
+        None         => return res
+    };
+    let root_path = fastcgi.root_path.clone().unwrap_or_else(|| server.root_path.clone());
+    let script = PathBuf::from(format!("{}{}", root_path, script_name));
+    let params = cgi::environment(&req, &root_path, &script, &script_name, &path_info);
+
+    let started = fastcgi.connection().and_then(|connection| {
+        let (request_id, records) = connection.begin(&params, &req.body)?;
+        Ok(ResponseReader {
+            connection,
+            request_id,
+            records,
+            buffer: vec![],
+            pos: 0,
+            completed: false,
+            timeout: fastcgi.timeout,
+            server: server.clone()
+        })
+    });
+    let mut reader = match started {
+        Ok(reader) => BufReader::new(reader),
+        Err(e) => {
+            server.log_error(&format!("Could not connect to FastCGI application {}: {}", fastcgi.address, e));
+            res.set_status(if e.kind() == io::ErrorKind::ResourceBusy { 503 } else { 502 });
+            res.end();
+            return res;
+        }
+    };
+
+    let headers = match read_headers(&mut reader) {
+        Ok(headers) => headers,
+        Err(e) => {
+            server.log_error(&format!("Invalid response from FastCGI application {}: {}", fastcgi.address, e));
+            res.set_status(if e.kind() == io::ErrorKind::TimedOut { 504 } else { 502 });
+            res.end();
+            return res;
+        }
+    };
+
+    if let Some(req) = cgi::local_redirect(&req, &headers) {
+        res.redirect_request(req);
+        return res;
+    }
+    cgi::set_response_head(&mut res, &headers);
+
+    if req.method == "HEAD" {
+        // Without the Content-Length of the application, the length of the
+        // body is unknown and left out with an empty streamed body.
+        if res.headers.contains_key("content-length") {
+            res.end();
+        } else {
+            res.send_reader(io::empty());
+        }
+    } else {
+        res.send_reader(reader);
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::TcpListener;
+
+    use http::log::{LogSink, Rotation};
+
+    fn decode_params(mut data: &[u8]) -> HashMap<String, String> {
+        let mut params = HashMap::new();
+        let decode_length = |data: &mut &[u8]| {
+            if data[0] < 128 {
+                let length = data[0] as usize;
+                *data = &data[1..];
+                length
+            } else {
+                let length = u32::from_be_bytes([data[0] & 0x7f, data[1], data[2], data[3]]) as usize;
+                *data = &data[4..];
+                length
+            }
+        };
+        while !data.is_empty() {
+            let name_length = decode_length(&mut data);
+            let value_length = decode_length(&mut data);
+            let name = String::from_utf8_lossy(&data[..name_length]).to_string();
+            let value = String::from_utf8_lossy(&data[name_length..name_length + value_length]).to_string();
+            params.insert(name, value);
+            data = &data[name_length + value_length..];
+        }
+
+        params
+    }
+
+    /// Start a FastCGI responder accepting a single connection, and replying
+    /// to `count` requests in the reverse order once it has received them all.
+    fn responder(count: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut params: HashMap<u16, Vec<u8>> = HashMap::new();
+            let mut stdin: HashMap<u16, Vec<u8>> = HashMap::new();
+            let mut completed = vec![];
+            while completed.len() < count {
+                let record = read_record(&mut stream).unwrap();
+                let id = record.request_id;
+                match record.kind {
+                    PARAMS => params.entry(id).or_default().extend(record.content),
+                    STDIN if record.content.is_empty() => completed.push(id),
+                    STDIN => stdin.entry(id).or_default().extend(record.content),
+                    _ => {}
+                }
+            }
+            for id in completed.into_iter().rev() {
+                let params = decode_params(&params[&id]);
+                let body = format!(
+                    "Content-Type: text/plain\r\n\r\n{} {} {}",
+                    params["SCRIPT_NAME"], params["PATH_INFO"], String::from_utf8_lossy(&stdin.remove(&id).unwrap_or_default())
+                );
+                write_record(&mut stream, STDERR, id, b"Notice").unwrap();
+                write_stream(&mut stream, STDOUT, id, body.as_bytes()).unwrap();
+                write_record(&mut stream, END_REQUEST, id, &[0; 8]).unwrap();
+            }
+        });
+
+        address
+    }
+
+    #[test]
+    fn test_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        match Socket::connect(&address, Duration::from_secs(5)).unwrap() {
+            Socket::Tcp(stream) => assert_eq!(stream.write_timeout().unwrap(), Some(Duration::from_secs(5))),
+            #[cfg(unix)]
+            Socket::Unix(_)     => unreachable!()
+        }
+    }
+
+    #[test]
+    fn test_begin() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let connection = Connection::open(&address, Duration::from_secs(5), true).unwrap();
+        let (_stream, _) = listener.accept().unwrap();
+
+        let (request_id, _records) = connection.begin(&[], b"").unwrap();
+        assert_eq!(request_id, 1);
+
+        // Every request id is in use
+        for id in 2..u16::MAX {
+            connection.requests.lock().unwrap().insert(id, mpsc::channel().0);
+        }
+        let (request_id, _records) = connection.begin(&[], b"").unwrap();
+        assert_eq!(request_id, u16::MAX);
+        let e = connection.begin(&[], b"").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::ResourceBusy);
+    }
+
+    #[test]
+    fn test_records() {
+        let mut data = vec![];
+        write_record(&mut data, STDOUT, 258, b"Hello").unwrap();
+        assert_eq!(data.len(), 16);
+        assert_eq!(&data[..8], &[1, STDOUT, 1, 2, 0, 5, 3, 0]);
+
+        let record = read_record(&mut &data[..]).unwrap();
+        assert_eq!(record, Record { kind: STDOUT, request_id: 258, content: b"Hello".to_vec() });
+
+        let params = vec![("SCRIPT_NAME".to_string(), "/index.php".to_string()), ("LONG".to_string(), "x".repeat(200))];
+        let decoded = decode_params(&encode_params(&params));
+        assert_eq!(decoded["SCRIPT_NAME"], "/index.php");
+        assert_eq!(decoded["LONG"].len(), 200);
+    }
+
+    #[test]
+    fn test_split_script() {
+        let mut fastcgi = FastCgi::new("127.0.0.1:9000");
+        assert_eq!(fastcgi.split_script("/wiki/index.php/Main_Page"), Some(("/wiki/index.php".into(), "/Main_Page".into())));
+        assert_eq!(fastcgi.split_script("/index.php"), Some(("/index.php".into(), "".into())));
+        assert_eq!(fastcgi.split_script("/index.html"), None);
+
+        fastcgi.path_prefix = Some("/app/".into());
+        assert_eq!(fastcgi.split_script("/app/users"), Some(("/app/users".into(), "".into())));
+    }
+
+    #[test]
+    fn test_handler() {
+        let mut server = Server::new();
+        let mut fastcgi = FastCgi::new(&responder(2));
+        fastcgi.multiplex = true;
+        fastcgi.timeout = Duration::from_secs(5);
+        server.fastcgi = Some(fastcgi);
+        server.error_log = LogSink::file("/dev/null", Rotation::Never, 0).unwrap();
+
+        // Both requests are sent on the same connection before any response
+        let threads: Vec<_> = ["alice", "bob"].iter().map(|&name| {
+            let server = server.clone();
+            thread::spawn(move || {
+                let mut req = Request::new("POST", "example.com", &format!("/index.php/{}", name));
+                req.body = name.as_bytes().to_vec();
+                let mut res = handler(req, Response::new(server));
+                let mut body = String::new();
+                res.take_reader().unwrap().read_to_string(&mut body).unwrap();
+                (res.status_code, res.headers.get("content-type").cloned(), body)
+            })
+        }).collect();
+
+        for (thread, name) in threads.into_iter().zip(["alice", "bob"].iter()) {
+            let (status_code, content_type, body) = thread.join().unwrap();
+            assert_eq!(status_code, 200);
+            assert_eq!(content_type, Some("text/plain".into()));
+            assert_eq!(body, format!("/index.php /{} {}", name, name));
+        }
+    }
+
+    #[test]
+    fn test_handler_head() {
+        let mut server = Server::new();
+        server.fastcgi = Some(FastCgi::new(&responder(1)));
+        server.error_log = LogSink::file("/dev/null", Rotation::Never, 0).unwrap();
+
+        let req = Request::new("HEAD", "example.com", "/index.php/info");
+        let mut res = handler(req, Response::new(server));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        res.write(&stream);
+        drop(stream);
+
+        let mut received = String::new();
+        client.read_to_string(&mut received).unwrap();
+        assert!(received.starts_with("HTTP/1.1 200 Ok\n"));
+        assert!(!received.contains("content-length"));
+        assert!(received.ends_with("\n\n"));
+    }
+}
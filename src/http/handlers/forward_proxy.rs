@@ -0,0 +1,425 @@
+use std::io;
+use std::io::BufReader;
+use std::io::prelude::*;
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+
+use http::cidr::Cidr;
+use http::glob;
+use http::handlers::basic_auth;
+use http::headers::Headers;
+use http::handlers::proxy;
+use http::request::{self, Request, TargetForm};
+use http::response::Response;
+use http::upgrade::Connection;
+
+/// Configuration of the forward proxy
+#[derive(Clone, Debug)]
+pub struct ForwardProxy {
+    /// Destinations allowed, as glob patterns of hosts with an optional
+    /// port, like `*.example.com:443` or `10.0.0.*`. The patterns of IPv4
+    /// addresses, like `10.0.0.*`, and the CIDR blocks, like `10.0.0.0/8` or
+    /// `[::1]`, are matched against the resolved address of the destination.
+    pub allowed: Vec<String>,
+
+    /// Credentials `user:password` required in the `Proxy-Authorization`
+    /// header of the requests, if any.
+    pub credentials: Option<String>,
+
+    /// Timeout of the connections to the destinations, of the reads and
+    /// writes of the requests forwarded to them, and of the idle tunnels.
+    pub timeout: Duration
+}
+
+impl ForwardProxy {
+    /// Create a forward proxy to the destinations matching the patterns
+    /// of `allowed`.
+    pub fn new(allowed: Vec<String>) -> ForwardProxy {
+        ForwardProxy {
+            allowed,
+            credentials: None,
+            timeout: Duration::from_secs(60)
+        }
+    }
+
+    /// Return `true` if the destination `host`, resolved to `addr`,
+    /// is allowed.
+    pub fn is_allowed(&self, host: &str, addr: &SocketAddr) -> bool {
+        self.allowed.iter().any(|pattern| {
+            match split_host_port(pattern) {
+                Some((pattern, None))          => host_matches(&pattern, host, addr),
+                Some((pattern, Some(allowed))) => host_matches(&pattern, host, addr) && allowed == addr.port(),
+                None => {
+                    // Pattern with any port, like `example.com:*`
+                    match pattern.strip_suffix(":*") {
+                        Some(pattern) => host_matches(&pattern.to_lowercase(), host, addr),
+                        None          => false
+                    }
+                }
+            }
+        })
+    }
+
+    /// Return `true` if the request has the credentials required by the
+    /// proxy in its `Proxy-Authorization` header.
+    ///
+    /// (RFC 7235 4.4, RFC 7617 2)
+    pub fn is_authorized(&self, req: &Request) -> bool {
+        let credentials = match self.credentials {
+            None                  => return true,
+            Some(ref credentials) => credentials
+        };
+        let authorization = req.headers.get("proxy-authorization").map_or("", |value| value.as_str());
+
+        match basic_auth::parse_credentials(authorization) {
+            Some((user, password)) => {
+                let decoded = format!("{}:{}", user, password);
+                basic_auth::constant_time_eq(decoded.as_bytes(), credentials.as_bytes())
+            },
+            None => false
+        }
+    }
+}
+
+/// Split an authority, like `example.com:443` or `[::1]:443`, into its
+/// lowercase host and its port, or return `None` if the port is invalid.
+fn split_host_port(authority: &str) -> Option<(String, Option<u16>)> {
+    let authority = authority.to_lowercase();
+    let (host, port) = match authority.rfind(':') {
+        Some(i) if !authority[i..].contains(']') => (&authority[..i], Some(&authority[i + 1..])),
+        _                                        => (authority.as_str(), None)
+    };
+    let port = match port {
+        Some(port) => Some(port.parse().ok()?),
+        None       => None
+    };
+
+    Some((host.to_string(), port))
+}
+
+/// Return `true` if the host pattern of a destination matches `host`, or
+/// its address `addr` for the pattern of an address, so that a host name
+/// like `10.0.0.1.example.com` does not match `10.0.0.*`.
+fn host_matches(pattern: &str, host: &str, addr: &SocketAddr) -> bool {
+    let ip = addr.ip().to_canonical();
+    let block = pattern.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(cidr) = Cidr::parse(block) {
+        return cidr.contains(&ip);
+    }
+
+    let is_ipv4_pattern = pattern.contains('.') && pattern.chars().all(|c| {
+        c.is_ascii_digit() || c == '.' || c == '*' || c == '?'
+    });
+    if is_ipv4_pattern {
+        ip.is_ipv4() && glob::matches(pattern, &ip.to_string())
+    } else {
+        glob::matches(pattern, host)
+    }
+}
+
+/// Resolve the address of the destination at `host` and `port`.
+fn resolve(host: &str, port: u16) -> io::Result<SocketAddr> {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    match (host, port).to_socket_addrs()?.next() {
+        Some(addr) => Ok(addr),
+        None       => Err(io::Error::other("Could not resolve address"))
+    }
+}
+
+/// Relay the bytes between the client and the destination in both
+/// directions, until both of them have closed their side of the connection
+/// or have been idle for `timeout`.
+pub fn tunnel(client: Connection, mut destination: TcpStream, timeout: Duration) {
+    let (mut client, buffered) = client.into_parts();
+    if client.set_read_timeout(Some(timeout)).and_then(|_| client.set_write_timeout(Some(timeout))).is_err() {
+        return;
+    }
+    let (mut client_reader, mut destination_writer) = match (client.try_clone(), destination.try_clone()) {
+        (Ok(client_reader), Ok(destination_writer)) => (client_reader, destination_writer),
+        _ => return
+    };
+
+    let upload = thread::spawn(move || {
+        let _ = destination_writer.write_all(&buffered).and_then(|_| {
+            io::copy(&mut client_reader, &mut destination_writer)
+        });
+        let _ = destination_writer.shutdown(Shutdown::Write);
+    });
+    let _ = io::copy(&mut destination, &mut client);
+    let _ = client.shutdown(Shutdown::Write);
+    let _ = upload.join();
+}
+
+/// Send the request to the destination and read the head of its response.
+fn forward(stream: TcpStream, req: &Request) -> io::Result<(u16, String, Headers, BufReader<TcpStream>)> {
+    let mut destination_req = req.clone();
+    destination_req.headers = proxy::end_to_end_headers(&req.headers);
+    destination_req.headers.remove("expect");
+    let via = format!("{} simpleton", req.version.trim_start_matches("HTTP/"));
+    proxy::append_header(&mut destination_req.headers, "via", &via);
+    destination_req.headers.set("connection", "close");
+    destination_req.version = "HTTP/1.1".into();
+    destination_req.send(&stream)?;
+
+    let mut reader = BufReader::new(stream);
+    loop {
+        let (status_code, status_message, headers) = proxy::read_head(&mut reader)?;
+
+        // Skip interim responses, like `100 Continue`
+        if status_code >= 200 {
+            return Ok((status_code, status_message, headers, reader));
+        }
+    }
+}
+
+/// Forward the requests sent to the server as a proxy with `Server::forward_proxy`:
+/// open a tunnel to the destination of `CONNECT` requests, and forward
+/// the requests with an absolute `http` URI.
+pub fn handler(req: Request, mut res: Response) -> Response {
+    if res.is_ended() {
+        return res;
+    }
+
+    let server = res.server.clone();
+    let proxy = match server.forward_proxy {
+        None            => return res,
+        Some(ref proxy) => proxy
+    };
+    let is_connect = req.target_form == TargetForm::Authority;
+    if !is_connect && req.target_form != TargetForm::Absolute {
+        return res;
+    }
+
+    if !proxy.is_authorized(&req) {
+        res.set_status(407);
+        res.headers.set("proxy-authenticate", "Basic realm=\"simpleton\"");
+        res.end();
+        return res;
+    }
+
+    // Only the `http` scheme can be forwarded, clients use `CONNECT`
+    // for `https` URIs.
+    let authority = if is_connect {
+        req.uri.clone()
+    } else {
+        let uri = req.original_uri.as_deref().unwrap_or("");
+        match request::split_absolute_uri(uri) {
+            Some((scheme, _, _)) if scheme.eq_ignore_ascii_case("http") => {},
+            _ => {
+                res.set_status(501);
+                res.end();
+                return res;
+            }
+        }
+        req.headers.get("host").cloned().unwrap_or_default()
+    };
+    let destination = match split_host_port(&authority) {
+        Some((host, Some(port)))          => Some((host, port)),
+        Some((host, None)) if !is_connect => Some((host, 80)),
+        _                                 => None
+    };
+    let (host, port) = match destination {
+        Some((host, port)) if !host.is_empty() => (host, port),
+        _ => {
+            res.set_status(400);
+            res.end();
+            return res;
+        }
+    };
+
+    // The destination is connected at the address it is allowed for
+    let addr = match resolve(&host, port) {
+        Ok(addr) => addr,
+        Err(e)   => {
+            server.log_error(&format!("Could not resolve {}:{}: {}", host, port, e));
+            res.set_status(502);
+            res.end();
+            return res;
+        }
+    };
+    if !proxy.is_allowed(&host, &addr) {
+        res.set_status(403);
+        res.end();
+        return res;
+    }
+
+    let timeout = proxy.timeout;
+    let stream = TcpStream::connect_timeout(&addr, timeout).and_then(|stream| {
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        Ok(stream)
+    });
+    let result = stream.and_then(|stream| {
+        if is_connect {
+            // A tunnel is established by a 2xx response without a body,
+            // after which the connection is relayed.
+            //
+            // (RFC 7231 4.3.6)
+            res.set_status(200);
+            res.on_upgrade(move |client| tunnel(client, stream, timeout));
+            res.end();
+            return Ok(());
+        }
+
+        let (status_code, status_message, headers, reader) = forward(stream, &req)?;
+        res.status_code = status_code;
+        res.status_message = status_message;
+        res.headers = proxy::end_to_end_headers(&headers);
+        proxy::append_header(&mut res.headers, "via", "1.1 simpleton");
+        proxy::send_body(&req, &mut res, &headers, reader);
+        Ok(())
+    });
+
+    if let Err(e) = result {
+        server.log_error(&format!("Could not proxy request to {}:{}: {}", host, port, e));
+        res.set_status(match e.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => 504,
+            _                                                   => 502
+        });
+        res.end();
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::TcpListener;
+
+    use http::log::{LogSink, Rotation};
+    use http::server::Server;
+
+    fn server(allowed: &str) -> Server {
+        let mut server = Server::new();
+        server.error_log = LogSink::file("/dev/null", Rotation::Never, 0).unwrap();
+        server.forward_proxy = Some(ForwardProxy::new(vec![allowed.into()]));
+        server
+    }
+
+    #[test]
+    fn test_split_host_port() {
+        assert_eq!(split_host_port("Example.com:443"), Some(("example.com".into(), Some(443))));
+        assert_eq!(split_host_port("example.com"), Some(("example.com".into(), None)));
+        assert_eq!(split_host_port("[::1]:8080"), Some(("[::1]".into(), Some(8080))));
+        assert_eq!(split_host_port("[::1]"), Some(("[::1]".into(), None)));
+        assert_eq!(split_host_port("example.com:https"), None);
+    }
+
+    #[test]
+    fn test_is_allowed() {
+        let patterns = vec!["*.example.com:443".to_string(), "10.0.0.*".into(), "example.org:*".into(), "192.0.2.0/24:80".into()];
+        let proxy = ForwardProxy::new(patterns);
+        let addr = |s: &str| s.parse::<SocketAddr>().unwrap();
+        assert!(proxy.is_allowed("www.example.com", &addr("198.51.100.1:443")));
+        assert!(!proxy.is_allowed("www.example.com", &addr("198.51.100.1:22")));
+        assert!(!proxy.is_allowed("example.com", &addr("198.51.100.1:443")));
+        assert!(proxy.is_allowed("10.0.0.5", &addr("10.0.0.5:22")));
+        assert!(proxy.is_allowed("example.org", &addr("198.51.100.1:8080")));
+        assert!(proxy.is_allowed("192.0.2.1", &addr("192.0.2.1:80")));
+        assert!(!proxy.is_allowed("192.0.2.1", &addr("192.0.2.1:443")));
+
+        // The patterns of addresses are matched against the resolved address
+        assert!(!proxy.is_allowed("10.0.0.evil.example", &addr("127.0.0.1:80")));
+        assert!(!proxy.is_allowed("192.0.2.1.evil.example", &addr("127.0.0.1:80")));
+        assert!(proxy.is_allowed("internal.example", &addr("10.0.0.5:80")));
+        assert!(proxy.is_allowed("internal.example", &addr("[::ffff:10.0.0.5]:80")));
+    }
+
+    #[test]
+    fn test_is_authorized() {
+        let mut proxy = ForwardProxy::new(vec![]);
+        let mut req = Request::new("GET", "example.com", "/");
+        assert!(proxy.is_authorized(&req));
+
+        proxy.credentials = Some("ci:secret".into());
+        assert!(!proxy.is_authorized(&req));
+        req.headers.set("proxy-authorization", "Basic Y2k6c2VjcmV0");
+        assert!(proxy.is_authorized(&req));
+        req.headers.set("proxy-authorization", "Basic Y2k6c2VjcmV1");
+        assert!(!proxy.is_authorized(&req));
+    }
+
+    #[test]
+    fn test_handler_connect() {
+        // Destination echoing what it receives
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = stream.try_clone().unwrap();
+            io::copy(&mut reader, &mut stream).unwrap();
+        });
+
+        let message = format!("CONNECT {} HTTP/1.1\nHost: {}", address, address);
+        let req = Request::from_str(&message).unwrap();
+        let mut res = handler(req.clone(), Response::new(server("127.0.0.1:22")));
+        assert_eq!(res.status_code, 403);
+        assert!(res.take_upgrade().is_none());
+
+        let mut res = handler(req, Response::new(server("127.0.0.1")));
+        assert_eq!(res.status_code, 200);
+        let upgrade = res.take_upgrade().unwrap();
+
+        let client_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(client_listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = client_listener.accept().unwrap();
+        let relay = thread::spawn(move || upgrade(Connection::new(stream, b"Hello, ".to_vec())));
+
+        client.write_all(b"World!").unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        let mut received = String::new();
+        client.read_to_string(&mut received).unwrap();
+        assert_eq!(received, "Hello, World!");
+        relay.join().unwrap();
+    }
+
+    #[test]
+    fn test_tunnel_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let destination = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (_destination, _) = listener.accept().unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        // The tunnel is closed once both sides have been idle
+        let timeout = Duration::from_millis(100);
+        destination.set_read_timeout(Some(timeout)).unwrap();
+        tunnel(Connection::new(stream, vec![]), destination, timeout);
+    }
+
+    #[test]
+    fn test_handler_absolute() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let destination = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            while reader.read_line(&mut request).unwrap() > 2 {}
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nHello").unwrap();
+            request
+        });
+
+        let message = format!("GET http://{}/status?page=2 HTTP/1.1\nHost: proxy\nProxy-Connection: keep-alive", address);
+        let req = Request::from_str(&message).unwrap();
+        let mut res = handler(req, Response::new(server("127.0.0.1:*")));
+
+        let request = destination.join().unwrap().to_lowercase();
+        assert!(request.starts_with("get /status?page=2 http/1.1\n"));
+        assert!(request.contains(&format!("host: {}\n", address)));
+        assert!(!request.contains("proxy-connection"));
+
+        assert_eq!(res.status_code, 200);
+        assert_eq!(res.headers.get("via"), Some(&"1.1 simpleton".into()));
+        let mut body = String::new();
+        res.take_reader().unwrap().read_to_string(&mut body).unwrap();
+        assert_eq!(body, "Hello");
+
+        let req = Request::from_str("GET https://example.com/ HTTP/1.1\nHost: example.com").unwrap();
+        let res = handler(req, Response::new(server("*")));
+        assert_eq!(res.status_code, 501);
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use http::request::Request;
+use http::response::Response;
+
+/// Upper bounds, in seconds, of the buckets of the request latency histogram.
+const BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Methods counted under their own name, other methods are counted as
+/// `OTHER` to bound the number of series.
+const METHODS: [&str; 9] = ["GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH"];
+
+/// Metrics of the server, shared between its threads
+#[derive(Clone)]
+pub struct Metrics {
+    counters: Arc<Mutex<Counters>>,
+    active_connections: Arc<AtomicUsize>
+}
+
+#[derive(Default)]
+struct Counters {
+    requests: BTreeMap<(String, u16), u64>,
+    buckets: [u64; 11],
+    duration_sum: f64,
+    duration_count: u64,
+    received_bytes: u64,
+    sent_bytes: u64
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            counters: Arc::new(Mutex::new(Counters::default())),
+            active_connections: Arc::new(AtomicUsize::new(0))
+        }
+    }
+
+    /// Count a request handled by the server, with its response, the time
+    /// taken to serve it in nanoseconds and the number of bytes exchanged.
+    pub fn record_request(&self, req: &Request, res: &Response, duration: u64, received_bytes: usize, sent_bytes: usize) {
+        let method = match METHODS.iter().find(|&&method| method == req.method) {
+            Some(method) => method,
+            None         => "OTHER"
+        };
+        let seconds = duration as f64 / 1_000_000_000.0;
+
+        let mut counters = self.counters.lock().unwrap();
+        *counters.requests.entry((method.into(), res.status_code)).or_insert(0) += 1;
+        for (i, &bound) in BUCKETS.iter().enumerate() {
+            if seconds <= bound {
+                counters.buckets[i] += 1;
+            }
+        }
+        counters.duration_sum += seconds;
+        counters.duration_count += 1;
+        counters.received_bytes += received_bytes as u64;
+        counters.sent_bytes += sent_bytes as u64;
+    }
+
+    /// Count a connection accepted by the server.
+    pub fn open_connection(&self) {
+        self.active_connections.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Count a connection closed by the server.
+    pub fn close_connection(&self) {
+        self.active_connections.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Render the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let counters = self.counters.lock().unwrap();
+        let mut lines = vec![];
+
+        lines.push("# HELP simpleton_http_requests_total Number of HTTP requests handled.".into());
+        lines.push("# TYPE simpleton_http_requests_total counter".into());
+        for (&(ref method, status), count) in &counters.requests {
+            lines.push(format!(
+                "simpleton_http_requests_total{{method=\"{}\",status=\"{}\"}} {}",
+                method, status, count
+            ));
+        }
+
+        lines.push("# HELP simpleton_http_request_duration_seconds Time taken to serve HTTP requests.".into());
+        lines.push("# TYPE simpleton_http_request_duration_seconds histogram".into());
+        for (i, bound) in BUCKETS.iter().enumerate() {
+            lines.push(format!(
+                "simpleton_http_request_duration_seconds_bucket{{le=\"{}\"}} {}",
+                bound, counters.buckets[i]
+            ));
+        }
+        lines.push(format!(
+            "simpleton_http_request_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            counters.duration_count
+        ));
+        lines.push(format!("simpleton_http_request_duration_seconds_sum {}", counters.duration_sum));
+        lines.push(format!("simpleton_http_request_duration_seconds_count {}", counters.duration_count));
+
+        lines.push("# HELP simpleton_http_received_bytes_total Number of bytes of HTTP requests received.".into());
+        lines.push("# TYPE simpleton_http_received_bytes_total counter".into());
+        lines.push(format!("simpleton_http_received_bytes_total {}", counters.received_bytes));
+
+        lines.push("# HELP simpleton_http_sent_bytes_total Number of bytes of HTTP responses sent.".into());
+        lines.push("# TYPE simpleton_http_sent_bytes_total counter".into());
+        lines.push(format!("simpleton_http_sent_bytes_total {}", counters.sent_bytes));
+
+        lines.push("# HELP simpleton_connections_active Number of connections currently open.".into());
+        lines.push("# TYPE simpleton_connections_active gauge".into());
+        lines.push(format!("simpleton_connections_active {}", self.active_connections.load(Ordering::SeqCst)));
+
+        lines.push("".into());
+        lines.join("\n")
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Metrics {
+        Metrics::new()
+    }
+}
+
+/// Serve the metrics of the server at `Server::metrics_path`.
+///
+/// The metrics are collected only when `Server::metrics` is set.
+pub fn handler(req: Request, mut res: Response) -> Response {
+    if res.is_ended() {
+        return res;
+    }
+
+    let server = res.server.clone();
+    let metrics = match server.metrics {
+        None              => return res,
+        Some(ref metrics) => metrics
+    };
+    if req.uri.split('?').next() != Some(&server.metrics_path) {
+        return res;
+    }
+
+    if req.method != "GET" && req.method != "HEAD" {
+        res.status_code = 405;
+        res.status_message = "Method Not Allowed".into();
+        res.headers.set("allow", "GET, HEAD");
+        res.end();
+        return res;
+    }
+
+    let body = metrics.render();
+    res.headers.set("content-type", "text/plain; version=0.0.4");
+    if req.method == "HEAD" {
+        res.headers.set("content-length", &body.len().to_string());
+        res.end();
+    } else {
+        res.send(body.as_bytes());
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use http::server::Server;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new();
+        let req = Request::new("GET", "example.com", "/");
+        let res = Response::new(Server::new());
+        metrics.record_request(&req, &res, 20_000_000, 100, 1000);
+        metrics.record_request(&req, &res, 2_000_000_000, 100, 1000);
+        let req = Request::new("BREW", "example.com", "/");
+        metrics.record_request(&req, &res, 1_000_000, 100, 1000);
+        metrics.open_connection();
+
+        let text = metrics.render();
+        assert!(text.contains("simpleton_http_requests_total{method=\"GET\",status=\"200\"} 2\n"));
+        assert!(text.contains("simpleton_http_requests_total{method=\"OTHER\",status=\"200\"} 1\n"));
+        assert!(text.contains("simpleton_http_request_duration_seconds_bucket{le=\"0.005\"} 1\n"));
+        assert!(text.contains("simpleton_http_request_duration_seconds_bucket{le=\"0.025\"} 2\n"));
+        assert!(text.contains("simpleton_http_request_duration_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(text.contains("simpleton_http_request_duration_seconds_count 3\n"));
+        assert!(text.contains("simpleton_http_received_bytes_total 300\n"));
+        assert!(text.contains("simpleton_http_sent_bytes_total 3000\n"));
+        assert!(text.contains("simpleton_connections_active 1\n"));
+    }
+
+    #[test]
+    fn test_handler() {
+        let mut server = Server::new();
+        server.metrics = Some(Metrics::new());
+
+        let req = Request::new("GET", "example.com", "/metrics");
+        let res = handler(req, Response::new(server.clone()));
+        assert!(res.is_ended());
+        assert!(res.body().starts_with(b"# HELP"));
+
+        let req = Request::new("GET", "example.com", "/index.html");
+        let res = handler(req, Response::new(server));
+        assert!(!res.is_ended());
+    }
+}
@@ -1,5 +1,35 @@
+/// HTTP Server handlers for allowing or denying clients by IP address
+pub mod acl;
+
+/// HTTP Server handlers for authenticating users with the Basic scheme
+pub mod basic_auth;
+
+/// HTTP Server handlers for authenticating users with the Digest scheme
+pub mod digest_auth;
+
+/// HTTP Server handlers for rewriting and redirecting requests
+pub mod rewrite;
+
 /// HTTP Server handlers for serving static files
 pub mod serve_static;
 
+/// HTTP Server handlers for giving a body to error responses
+pub mod error_pages;
+
+/// HTTP Server handlers for executing CGI scripts
+pub mod cgi;
+
+/// HTTP Server handlers for forwarding requests to FastCGI applications
+pub mod fastcgi;
+
+/// HTTP Server handlers for proxying requests to upstream servers
+pub mod proxy;
+
+/// HTTP Server handlers for forwarding requests as a forward proxy
+pub mod forward_proxy;
+
+/// HTTP Server handlers for serving metrics
+pub mod metrics;
+
 /// HTTP Server handlers for printing logs
 pub mod print_log;
@@ -1,18 +1,295 @@
+use time;
+
+use http::escape;
 use http::request::Request;
 use http::response::Response;
 
+/// Template of the Common Log Format.
+pub const COMMON: &str = "%h %l %u %t \"%r\" %>s %b";
+
+/// Template of the Combined Log Format.
+pub const COMBINED: &str = "%h %l %u %t \"%r\" %>s %b \"%{Referer}i\" \"%{User-Agent}i\"";
+
+/// Format of the lines of the access log
+#[derive(Clone, Debug, PartialEq)]
+pub enum LogFormat {
+    /// Common Log Format
+    Common,
+
+    /// Combined Log Format, adding the referer and the user agent
+    /// of the request to the Common Log Format.
+    Combined,
+
+    /// One JSON object per line
+    Json,
+
+    /// Template made of the directives of the `LogFormat` of Apache,
+    /// for example `%h %t "%r" %>s %b %D`.
+    Custom(String)
+}
+
+impl LogFormat {
+    /// Get a log format from its name (`common`, `combined` or `json`),
+    /// any other name is used as a custom template.
+    pub fn from_name(name: &str) -> LogFormat {
+        match name {
+            "common"   => LogFormat::Common,
+            "combined" => LogFormat::Combined,
+            "json"     => LogFormat::Json,
+            template   => LogFormat::Custom(template.into())
+        }
+    }
+}
+
 /// Run after every other handlers to print a log of the request
-/// and its response to stdout.
-pub fn handler(req: Request, res: Response) -> Response {
-    println!(
-        "{} - - [{}] \"{} {} {}\" {} -",
-        req.ip,
-        res.date,
-        req.method,
-        req.uri,
-        req.version,
-        res.status_code
-    );
+/// and its response to the access log of the server.
+pub fn handler(req: Request, mut res: Response) -> Response {
+    // The response of a request that none of the handlers could handle
+    // is sent with a 404 status code, which is the one to log.
+    if !res.is_ended() {
+        res.set_status(404);
+        res.end();
+    }
+    res.server.access_log.write_line(&format(&req, &res));
 
     res
 }
+
+/// Format the log line of the request and its response using
+/// the log format of the server.
+pub fn format(req: &Request, res: &Response) -> String {
+    match res.server.log_format {
+        LogFormat::Common               => format_template(COMMON, req, res),
+        LogFormat::Combined             => format_template(COMBINED, req, res),
+        LogFormat::Json                 => format_json(req, res),
+        LogFormat::Custom(ref template) => format_template(template, req, res)
+    }
+}
+
+/// Format a log line from a template using the following directives:
+///
+/// - `%%`: the percent sign
+/// - `%a`, `%h`: the IP address of the client
+/// - `%{c}a`: the IP address of the peer, the client or a trusted proxy
+/// - `%l`: the remote logname (always `-`)
+/// - `%u`: the user authenticated by a handler
+/// - `%t`: the time the request was received
+/// - `%r`: the request line
+/// - `%m`: the method of the request
+/// - `%U`: the path of the request URI
+/// - `%q`: the query string of the request URI (prepended with `?`)
+/// - `%H`: the protocol of the request
+/// - `%s`, `%>s`: the status code of the response
+/// - `%b`: the size of the response body in bytes (`-` for no bytes)
+/// - `%B`: the size of the response body in bytes
+/// - `%D`: the time taken to serve the request in microseconds
+/// - `%T`: the time taken to serve the request in seconds
+/// - `%{Name}i`: the value of the header `Name` of the request
+/// - `%{Name}o`: the value of the header `Name` of the response
+pub fn format_template(template: &str, req: &Request, res: &Response) -> String {
+    let mut line = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            line.push(c);
+            continue;
+        }
+
+        // Optional argument between braces, like in `%{Referer}i`
+        let mut arg = None;
+        if chars.peek() == Some(&'{') {
+            chars.next();
+            let mut name = String::new();
+            for c in chars.by_ref() {
+                if c == '}' {
+                    break;
+                }
+                name.push(c);
+            }
+            arg = Some(name);
+        }
+
+        // Optional modifiers, like in `%>s`
+        while chars.peek() == Some(&'>') || chars.peek() == Some(&'<') {
+            chars.next();
+        }
+
+        let directive = match chars.next() {
+            None    => { line.push('%'); break },
+            Some(d) => d
+        };
+        let value = match (directive, arg) {
+            ('%', _)          => "%".into(),
+            ('a', Some(arg))  => if arg == "c" { req.peer_ip.clone() } else { req.ip.clone() },
+            ('a', None)       => req.ip.clone(),
+            ('h', _)          => req.ip.clone(),
+            ('l', _)          => "-".into(),
+            ('u', _)          => req.user.as_ref().map_or("-".into(), |user| escape_value(user)),
+            ('t', _)          => format!("[{}]", time::strftime("%d/%b/%Y:%H:%M:%S %z", &req.start_time).unwrap()),
+            ('r', _)          => escape_value(&format!("{} {} {}", req.method, request_uri(req), req.version)),
+            ('m', _)          => escape_value(&req.method),
+            ('U', _)          => escape_value(path(&req.uri)),
+            ('q', _)          => escape_value(&query(&req.uri)),
+            ('H', _)          => escape_value(&req.version),
+            ('s', _)          => res.status_code.to_string(),
+            ('b', _)          => match res.body_sent() {
+                0 => "-".into(),
+                n => n.to_string()
+            },
+            ('B', _)          => res.body_sent().to_string(),
+            ('D', _)          => duration(req).num_microseconds().unwrap_or(0).to_string(),
+            ('T', _)          => duration(req).num_seconds().to_string(),
+            ('i', Some(name)) => req.headers.get(&name).map_or("-".into(), |v| escape_value(v)),
+            ('o', Some(name)) => res.headers.get(&name).map_or("-".into(), |v| escape_value(v)),
+            (d, _)            => format!("%{}", d)
+        };
+        line.push_str(&value);
+    }
+
+    line
+}
+
+/// Format a log line as a JSON object.
+pub fn format_json(req: &Request, res: &Response) -> String {
+    let header = |name| match req.headers.get(name) {
+        None        => "null".into(),
+        Some(value) => format!("\"{}\"", escape::json(value))
+    };
+
+    format!(
+        "{{\"time\":\"{}\",\"remote_addr\":\"{}\",\"peer_addr\":\"{}\",\"remote_user\":{},\"method\":\"{}\",\"uri\":\"{}\",\"protocol\":\"{}\",\"status\":{},\"size\":{},\"duration_us\":{},\"referer\":{},\"user_agent\":{}}}",
+        time::strftime("%Y-%m-%dT%H:%M:%S%z", &req.start_time).unwrap(),
+        escape::json(&req.ip),
+        escape::json(&req.peer_ip),
+        req.user.as_ref().map_or("null".into(), |user| format!("\"{}\"", escape::json(user))),
+        escape::json(&req.method),
+        escape::json(request_uri(req)),
+        escape::json(&req.version),
+        res.status_code,
+        res.body_sent(),
+        duration(req).num_microseconds().unwrap_or(0),
+        header("referer"),
+        header("user-agent")
+    )
+}
+
+/// Time taken to serve the request.
+fn duration(req: &Request) -> time::Duration {
+    time::Duration::nanoseconds(req.timings.total() as i64)
+}
+
+/// Request-URI received from the client, before any rewrite.
+fn request_uri(req: &Request) -> &str {
+    req.original_uri.as_ref().unwrap_or(&req.uri)
+}
+
+fn path(uri: &str) -> &str {
+    uri.split('?').next().unwrap()
+}
+
+fn query(uri: &str) -> String {
+    match uri.find('?') {
+        None    => String::new(),
+        Some(i) => uri[i..].into()
+    }
+}
+
+/// Escape quotes, backslashes and non-printable characters to prevent
+/// the values sent by clients from forging log lines.
+fn escape_value(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '"'  => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => {
+                escaped.push_str(&format!("\\x{:02x}", c as u32))
+            },
+            _    => escaped.push(c)
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+
+    use http::log::{LogSink, Rotation};
+    use http::server::Server;
+
+    fn request() -> Request {
+        let mut req = Request::new("GET", "example.com", "/index.html?lang=en");
+        req.ip = "127.0.0.1".into();
+        req.peer_ip = "10.0.0.1".into();
+        req.headers.set("referer", "http://example.com/");
+        req.headers.set("user-agent", "Mozilla/5.0 \"Test\"");
+
+        req
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(LogFormat::from_name("common"), LogFormat::Common);
+        assert_eq!(LogFormat::from_name("json"), LogFormat::Json);
+        assert_eq!(LogFormat::from_name("%h %s"), LogFormat::Custom("%h %s".into()));
+    }
+
+    #[test]
+    fn test_format_template() {
+        let req = request();
+        let res = Response::new(Server::new());
+
+        let line = format_template(COMMON, &req, &res);
+        assert!(line.starts_with("127.0.0.1 - - ["));
+        assert!(line.ends_with("] \"GET /index.html?lang=en HTTP/1.1\" 200 -"));
+
+        let line = format_template(COMBINED, &req, &res);
+        assert!(line.ends_with(" 200 - \"http://example.com/\" \"Mozilla/5.0 \\\"Test\\\"\""));
+
+        let line = format_template("%m %U %q %{X-Missing}i 100%% %B %x", &req, &res);
+        assert_eq!(line, "GET /index.html ?lang=en - 100% 0 %x");
+
+        let line = format_template("%a %{c}a", &req, &res);
+        assert_eq!(line, "127.0.0.1 10.0.0.1");
+
+        let mut req = req;
+        req.user = Some("alice \"admin\"".into());
+        let line = format_template(COMMON, &req, &res);
+        assert!(line.starts_with("127.0.0.1 - alice \\\"admin\\\" ["));
+    }
+
+    #[test]
+    fn test_format_json() {
+        let req = request();
+        let res = Response::new(Server::new());
+
+        let line = format_json(&req, &res);
+        assert!(line.starts_with("{\"time\":\""));
+        assert!(line.contains("\"remote_addr\":\"127.0.0.1\",\"peer_addr\":\"10.0.0.1\",\"remote_user\":null,"));
+        assert!(line.contains("\"status\":200,\"size\":0,"));
+        assert!(line.ends_with("\"user_agent\":\"Mozilla/5.0 \\\"Test\\\"\"}"));
+    }
+
+    #[test]
+    fn test_handler() {
+        let path = env::temp_dir().join(format!("simpleton-access-{}", time::precise_time_ns()));
+        let path = path.to_str().unwrap();
+        let mut server = Server::new();
+        server.access_log = LogSink::file(path, Rotation::Never, 0).unwrap();
+
+        // The status code of a response not handled is the one of the
+        // fallback of the server
+        let res = handler(request(), Response::new(server.clone()));
+        assert_eq!(res.status_code, 404);
+        assert!(res.is_ended());
+        server.access_log.flush();
+        assert!(fs::read_to_string(path).unwrap().contains("\" 404 "));
+
+        fs::remove_file(path).unwrap();
+    }
+}
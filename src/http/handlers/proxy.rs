@@ -0,0 +1,588 @@
+use std::io;
+use std::io::BufReader;
+use std::io::prelude::*;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::slice;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use http::chunked::ChunkedReader;
+use http::handlers::{basic_auth, forward_proxy};
+use http::headers::Headers;
+use http::request::Request;
+use http::response::Response;
+use http::upgrade;
+
+/// Headers meaningful only for a single connection, which must not be
+/// forwarded by proxies, in addition to those listed in `Connection`.
+///
+/// (RFC 7230 6.1)
+const HOP_BY_HOP_HEADERS: [&str; 9] = [
+    "connection", "keep-alive", "proxy-authenticate", "proxy-authorization",
+    "proxy-connection", "te", "trailer", "transfer-encoding", "upgrade"
+];
+
+/// Methods of the requests that can be sent again to another upstream
+/// after a failure.
+const IDEMPOTENT_METHODS: [&str; 6] = ["GET", "HEAD", "OPTIONS", "TRACE", "PUT", "DELETE"];
+
+/// State of an upstream server
+struct Upstream {
+    address: String,
+
+    /// Number of consecutive failures.
+    failures: u32,
+
+    /// Time of the last failure.
+    failed_at: Option<Instant>
+}
+
+/// Configuration of the reverse proxy, shared between the threads
+/// of the server
+#[derive(Clone)]
+pub struct Proxy {
+    /// Prefix of the paths of the requests forwarded to the upstreams.
+    pub path_prefix: String,
+
+    /// Forward the `Host` header of the client instead of replacing it
+    /// by the address of the upstream.
+    pub preserve_host: bool,
+
+    /// Number of consecutive failures after which an upstream is not used
+    /// anymore during `fail_timeout`.
+    pub max_fails: u32,
+    pub fail_timeout: Duration,
+
+    /// Timeout of the connections to the upstreams and of their reads
+    /// and writes.
+    pub timeout: Duration,
+
+    upstreams: Arc<Mutex<Vec<Upstream>>>,
+    next: Arc<AtomicUsize>
+}
+
+/// Head of a response from an upstream, with the reader of its body
+struct UpstreamResponse {
+    status_code: u16,
+    status_message: String,
+    headers: Headers,
+    reader: BufReader<TcpStream>
+}
+
+impl Proxy {
+    /// Create a proxy to the upstreams at `addresses`, like `127.0.0.1:8080`,
+    /// used in turn.
+    pub fn new(addresses: Vec<String>) -> Proxy {
+        let upstreams = addresses.into_iter().map(|address| {
+            Upstream { address, failures: 0, failed_at: None }
+        }).collect();
+
+        Proxy {
+            path_prefix: "/".into(),
+            preserve_host: false,
+            max_fails: 1,
+            fail_timeout: Duration::from_secs(10),
+            timeout: Duration::from_secs(60),
+            upstreams: Arc::new(Mutex::new(upstreams)),
+            next: Arc::new(AtomicUsize::new(0))
+        }
+    }
+
+    /// Get the addresses of the available upstreams, in the order in which
+    /// they should be tried for the next request.
+    fn candidates(&self) -> Vec<String> {
+        let upstreams = self.upstreams.lock().unwrap();
+        let n = upstreams.len();
+        if n == 0 {
+            return vec![];
+        }
+
+        let start = self.next.fetch_add(1, Ordering::SeqCst) % n;
+        (0..n).map(|i| &upstreams[(start + i) % n]).filter(|upstream| {
+            match upstream.failed_at {
+                Some(failed_at) if upstream.failures >= self.max_fails => {
+                    failed_at.elapsed() >= self.fail_timeout
+                },
+                _ => true
+            }
+        }).map(|upstream| upstream.address.clone()).collect()
+    }
+
+    /// Record the success or the failure of a request to the upstream
+    /// at `address`.
+    fn record(&self, address: &str, success: bool) {
+        let mut upstreams = self.upstreams.lock().unwrap();
+        if let Some(upstream) = upstreams.iter_mut().find(|upstream| upstream.address == address) {
+            if success {
+                upstream.failures = 0;
+                upstream.failed_at = None;
+            } else {
+                upstream.failures += 1;
+                upstream.failed_at = Some(Instant::now());
+            }
+        }
+    }
+}
+
+/// Return `true` if the header `name` must not be forwarded, given the
+/// value of the `Connection` header.
+fn is_hop_by_hop(name: &str, connection: &str) -> bool {
+    HOP_BY_HOP_HEADERS.contains(&name) || connection.split(',').any(|token| {
+        token.trim().eq_ignore_ascii_case(name)
+    })
+}
+
+/// Copy the end-to-end headers of a message.
+pub fn end_to_end_headers(headers: &Headers) -> Headers {
+    let connection = headers.get("connection").cloned().unwrap_or_default();
+    let mut result = Headers::new();
+    for (name, value) in headers {
+        if !is_hop_by_hop(&name, &connection) {
+            result.add(&name, &value);
+        }
+    }
+
+    result
+}
+
+/// Append `value` to the list of elements of the header `name`.
+pub fn append_header(headers: &mut Headers, name: &str, value: &str) {
+    let value = match headers.get(name) {
+        Some(values) => format!("{}, {}", values, value),
+        None         => value.into()
+    };
+    headers.set(name, &value);
+}
+
+/// Quote `value` as a quoted-string, escaping its backslashes and double
+/// quotes.
+///
+/// (RFC 7230 3.2.6)
+fn quoted_string(value: &str) -> String {
+    let mut result = String::from("\"");
+    for c in value.chars() {
+        if c == '\\' || c == '"' {
+            result.push('\\');
+        }
+        result.push(c);
+    }
+    result.push('"');
+    result
+}
+
+/// Get the protocols of the `Upgrade` header if the client has asked
+/// to switch to one of them, like `websocket`.
+///
+/// (RFC 7230 6.7)
+fn requested_upgrade(req: &Request) -> Option<&String> {
+    if req.version != "HTTP/1.1" || !upgrade::has_token(req.headers.get("connection"), "upgrade") {
+        return None;
+    }
+    req.headers.get("upgrade")
+}
+
+/// Build the request forwarded to the upstream at `address`.
+fn upstream_request(proxy: &Proxy, address: &str, req: &Request) -> Request {
+    let mut upstream_req = req.clone();
+    upstream_req.headers = end_to_end_headers(&req.headers);
+
+    // The body of the request has already been received
+    upstream_req.headers.remove("expect");
+
+    let host = req.headers.get("host").cloned().unwrap_or_default();
+    append_header(&mut upstream_req.headers, "x-forwarded-for", &req.peer_ip);
+    upstream_req.headers.set("x-forwarded-host", &host);
+    upstream_req.headers.set("x-forwarded-proto", &req.scheme);
+
+    // IPv6 addresses are quoted in the `Forwarded` header
+    //
+    // (RFC 7239 6)
+    let peer_ip = &req.peer_ip;
+    let node = if peer_ip.contains(':') { format!("\"[{}]\"", peer_ip) } else { peer_ip.clone() };
+    let forwarded = format!("for={};host={};proto={}", node, quoted_string(&host), req.scheme);
+    append_header(&mut upstream_req.headers, "forwarded", &forwarded);
+    append_header(&mut upstream_req.headers, "via", &format!("{} simpleton", protocol_version(&req.version)));
+
+    if !proxy.preserve_host {
+        upstream_req.headers.set("host", address);
+    }
+
+    // The upgrade is forwarded, the connection being relayed in both
+    // directions if the upstream switches protocols.
+    match requested_upgrade(req) {
+        Some(protocols) => {
+            upstream_req.headers.set("upgrade", protocols);
+            upstream_req.headers.set("connection", "upgrade");
+        },
+        None => upstream_req.headers.set("connection", "close")
+    }
+    upstream_req.version = "HTTP/1.1".into();
+
+    upstream_req
+}
+
+/// Get the protocol version of the `Via` header, like `1.1` for `HTTP/1.1`.
+fn protocol_version(version: &str) -> &str {
+    version.trim_start_matches("HTTP/")
+}
+
+/// Send `req` to the upstream at `address` and read the head of its response,
+/// or return the error and a boolean indicating if the request may have been
+/// received by the upstream.
+fn forward(proxy: &Proxy, address: &str, req: &Request) -> Result<UpstreamResponse, (io::Error, bool)> {
+    let addr = match address.to_socket_addrs().map(|mut addrs| addrs.next()) {
+        Ok(Some(addr)) => addr,
+        Ok(None)       => return Err((io::Error::other("Could not resolve address"), false)),
+        Err(e)         => return Err((e, false))
+    };
+    let stream = TcpStream::connect_timeout(&addr, proxy.timeout).map_err(|e| (e, false))?;
+    let _ = stream.set_read_timeout(Some(proxy.timeout));
+    let _ = stream.set_write_timeout(Some(proxy.timeout));
+
+    let mut upstream_req = upstream_request(proxy, address, req);
+    upstream_req.send(&stream).map_err(|e| (e, true))?;
+
+    let mut reader = BufReader::new(stream);
+    loop {
+        let res = read_head(&mut reader).map_err(|e| (e, true))?;
+
+        // A switch of protocols ends the response, but only if the client
+        // has asked for it.
+        if res.0 == 101 && requested_upgrade(req).is_none() {
+            let e = io::Error::new(io::ErrorKind::InvalidData, "Unexpected switch of protocols from upstream");
+            return Err((e, true));
+        }
+
+        // Skip interim responses, like `100 Continue`
+        if res.0 >= 200 || res.0 == 101 {
+            return Ok(UpstreamResponse {
+                status_code: res.0,
+                status_message: res.1,
+                headers: res.2,
+                reader
+            });
+        }
+    }
+}
+
+/// Read the status-line and the headers of a response.
+pub fn read_head<R: BufRead>(reader: &mut R) -> io::Result<(u16, String, Headers)> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid response from upstream");
+
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let mut fields = line.trim().splitn(3, ' ');
+    if !fields.next().unwrap_or("").starts_with("HTTP/") {
+        return Err(invalid());
+    }
+    let status_code = fields.next().and_then(|code| code.parse().ok()).ok_or_else(invalid)?;
+    let status_message = fields.next().unwrap_or("").to_string();
+
+    let mut headers = Headers::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let line = line.trim_end_matches(&['\r', '\n'][..]);
+        if line.is_empty() {
+            break;
+        }
+        let mut fields = line.splitn(2, ':');
+        if let (Some(name), Some(value)) = (fields.next(), fields.next()) {
+            // The values of `Set-Cookie` cannot be combined in a list since
+            // they can contain commas, like in `Expires`.
+            //
+            // (RFC 6265 3)
+            let name = name.trim();
+            if name.eq_ignore_ascii_case("set-cookie") {
+                headers.add(name, value.trim());
+            } else {
+                append_header(&mut headers, name, value.trim());
+            }
+        }
+    }
+
+    Ok((status_code, status_message, headers))
+}
+
+/// Stream back the body of the response of an upstream with `headers`,
+/// decoded from the chunked transfer coding if it was used, then end
+/// the response.
+pub fn send_body(req: &Request, res: &mut Response, headers: &Headers, reader: BufReader<TcpStream>) {
+    let has_body = req.method != "HEAD" && res.status_code != 204 && res.status_code != 304;
+    let is_chunked = headers.get("transfer-encoding").is_some_and(|coding| {
+        coding.to_lowercase().contains("chunked")
+    });
+    let content_length = headers.get("content-length").and_then(|length| {
+        length.trim().parse::<u64>().ok()
+    });
+    if !has_body {
+        res.end();
+    } else if is_chunked {
+        res.headers.remove("content-length");
+        res.send_reader(ChunkedReader::new(reader));
+    } else if let Some(length) = content_length {
+        res.send_reader(reader.take(length));
+    } else {
+        res.send_reader(reader);
+    }
+}
+
+/// End the response switching protocols with the `Upgrade` header of the
+/// upstream, then relay the connection of the client to the upstream with
+/// the bytes already received from it.
+fn relay(res: &mut Response, proxy: &Proxy, headers: &Headers, reader: BufReader<TcpStream>) {
+    let protocols = headers.get("upgrade").cloned().unwrap_or_default();
+    res.headers.set("upgrade", &protocols);
+    res.headers.set("connection", "Upgrade");
+
+    let buffered = reader.buffer().to_vec();
+    let upstream = reader.into_inner();
+    let timeout = proxy.timeout;
+    res.on_upgrade(move |mut client| {
+        if client.write_all(&buffered).is_ok() {
+            forward_proxy::tunnel(client, upstream, timeout);
+        }
+    });
+    res.end();
+}
+
+/// Forward requests whose path starts with the prefix of `Server::proxy`
+/// to its upstreams, and stream their responses back to the client.
+pub fn handler(req: Request, mut res: Response) -> Response {
+    if res.is_ended() {
+        return res;
+    }
+
+    let server = res.server.clone();
+    let proxy = match server.proxy {
+        None            => return res,
+        Some(ref proxy) => proxy
+    };
+    if !basic_auth::is_protected(slice::from_ref(&proxy.path_prefix), &req.decoded_path()) {
+        return res;
+    }
+
+    let mut status_code = 502;
+    for address in proxy.candidates() {
+        let upstream_res = match forward(proxy, &address, &req) {
+            Ok(upstream_res) => upstream_res,
+            Err((e, sent)) => {
+                proxy.record(&address, false);
+                server.log_error(&format!("Could not proxy request to {}: {}", address, e));
+                status_code = match e.kind() {
+                    io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => 504,
+                    _                                                   => 502
+                };
+
+                // A request that may have been processed by the upstream
+                // is sent again only if it is idempotent.
+                if sent && !IDEMPOTENT_METHODS.contains(&req.method.as_str()) {
+                    break;
+                }
+                continue;
+            }
+        };
+        proxy.record(&address, true);
+
+        res.status_code = upstream_res.status_code;
+        res.status_message = upstream_res.status_message;
+        res.headers = end_to_end_headers(&upstream_res.headers);
+        append_header(&mut res.headers, "via", "1.1 simpleton");
+
+        // Redirections to the upstream are rewritten to the proxy
+        if let Some(location) = res.headers.get("location").cloned() {
+            let upstream_url = format!("http://{}", address);
+            if let Some(path) = location.strip_prefix(&upstream_url) {
+                if path.is_empty() || path.starts_with('/') {
+                    let host = req.headers.get("host").cloned().unwrap_or_default();
+                    res.headers.set("location", &format!("{}://{}{}", req.scheme, host, path));
+                }
+            }
+        }
+
+        if upstream_res.status_code == 101 {
+            relay(&mut res, proxy, &upstream_res.headers, upstream_res.reader);
+            return res;
+        }
+
+        send_body(&req, &mut res, &upstream_res.headers, upstream_res.reader);
+        return res;
+    }
+
+    res.set_status(status_code);
+    res.end();
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::{Shutdown, TcpListener};
+    use std::thread;
+
+    use http::log::{LogSink, Rotation};
+    use http::server::Server;
+    use http::upgrade::Connection;
+
+    /// Start an upstream answering a single request with `response`, where
+    /// `UPSTREAM` is replaced by its address, and return its address and
+    /// the thread returning the head of the request it read.
+    fn upstream(response: &'static str) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let response = response.replace("UPSTREAM", &address);
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                request.push_str(&line);
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+            request
+        });
+
+        (address, handle)
+    }
+
+    #[test]
+    fn test_is_hop_by_hop() {
+        assert!(is_hop_by_hop("transfer-encoding", ""));
+        assert!(is_hop_by_hop("x-secret", "close, X-Secret"));
+        assert!(!is_hop_by_hop("content-type", "close"));
+    }
+
+    #[test]
+    fn test_quoted_string() {
+        assert_eq!(quoted_string("example.com"), "\"example.com\"");
+        assert_eq!(quoted_string("a\";for=\\b"), "\"a\\\";for=\\\\b\"");
+    }
+
+    #[test]
+    fn test_candidates() {
+        let addresses = vec!["a:80".to_string(), "b:80".to_string(), "c:80".to_string()];
+        let proxy = Proxy::new(addresses);
+        assert_eq!(proxy.candidates(), vec!["a:80", "b:80", "c:80"]);
+        assert_eq!(proxy.candidates(), vec!["b:80", "c:80", "a:80"]);
+
+        proxy.record("c:80", false);
+        assert_eq!(proxy.candidates(), vec!["a:80", "b:80"]);
+
+        proxy.record("c:80", true);
+        assert_eq!(proxy.candidates(), vec!["a:80", "b:80", "c:80"]);
+    }
+
+    #[test]
+    fn test_handler() {
+        let (address, upstream) = upstream(
+            "HTTP/1.1 302 Found\r\nLocation: http://UPSTREAM/login\r\nTransfer-Encoding: chunked\r\nConnection: close, X-Internal\r\nX-Internal: 1\r\nSet-Cookie: a=1; Expires=Wed, 21 Oct 2026 07:28:00 GMT\r\nSet-Cookie: b=2\r\n\r\n5\r\nHello\r\n0\r\n\r\n"
+        );
+        let mut server = Server::new();
+        server.proxy = Some(Proxy::new(vec![address.clone()]));
+
+        let mut req = Request::new("GET", "example.com", "/");
+        req.ip = "192.0.2.1".into();
+        req.peer_ip = "192.0.2.1".into();
+        req.headers.set("connection", "keep-alive");
+        let mut res = handler(req, Response::new(server));
+
+        let request = upstream.join().unwrap().to_lowercase();
+        assert!(request.contains(&format!("host: {}\n", address)));
+        assert!(request.contains("x-forwarded-for: 192.0.2.1\n"));
+        assert!(request.contains("forwarded: for=192.0.2.1;host=\"example.com\";proto=http\n"));
+        assert!(request.contains("via: 1.1 simpleton\n"));
+        assert!(request.contains("connection: close\n"));
+
+        assert_eq!(res.status_code, 302);
+        assert_eq!(res.headers.get("location"), Some(&"http://example.com/login".into()));
+        assert!(!res.headers.contains_key("transfer-encoding"));
+        assert!(!res.headers.contains_key("x-internal"));
+        assert_eq!(res.headers.get_all("set-cookie"), ["a=1; Expires=Wed, 21 Oct 2026 07:28:00 GMT".to_string(), "b=2".to_string()]);
+        let mut body = String::new();
+        res.take_reader().unwrap().read_to_string(&mut body).unwrap();
+        assert_eq!(body, "Hello");
+    }
+
+    #[test]
+    fn test_handler_unavailable() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let mut server = Server::new();
+        server.error_log = LogSink::file("/dev/null", Rotation::Never, 0).unwrap();
+        server.proxy = Some(Proxy::new(vec![address]));
+
+        let req = Request::new("GET", "example.com", "/");
+        let res = handler(req, Response::new(server.clone()));
+        assert_eq!(res.status_code, 502);
+
+        // The upstream is down until the end of the fail timeout
+        assert!(server.proxy.unwrap().candidates().is_empty());
+    }
+
+    #[test]
+    fn test_handler_path_prefix() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let mut server = Server::new();
+        server.error_log = LogSink::file("/dev/null", Rotation::Never, 0).unwrap();
+        let mut proxy = Proxy::new(vec![address]);
+        proxy.path_prefix = "/api".into();
+        server.proxy = Some(proxy);
+
+        let req = Request::new("GET", "example.com", "/apis");
+        let res = handler(req, Response::new(server.clone()));
+        assert!(!res.is_ended());
+
+        let req = Request::new("GET", "example.com", "/%61pi/users");
+        let res = handler(req, Response::new(server));
+        assert_eq!(res.status_code, 502);
+    }
+
+    #[test]
+    fn test_handler_upgrade() {
+        let (address, upstream) = upstream(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\nHello, "
+        );
+        let mut server = Server::new();
+        server.proxy = Some(Proxy::new(vec![address]));
+
+        let mut req = Request::new("GET", "example.com", "/chat");
+        req.headers.set("upgrade", "websocket");
+        req.headers.set("connection", "Upgrade");
+        let mut res = handler(req, Response::new(server));
+
+        let request = upstream.join().unwrap().to_lowercase();
+        assert!(request.contains("upgrade: websocket\n"));
+        assert!(request.contains("connection: upgrade\n"));
+
+        assert_eq!(res.status_code, 101);
+        assert_eq!(res.headers.get("upgrade"), Some(&"websocket".into()));
+        let upgrade = res.take_upgrade().unwrap();
+
+        let client_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(client_listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = client_listener.accept().unwrap();
+        let relay = thread::spawn(move || upgrade(Connection::new(stream, vec![])));
+
+        client.shutdown(Shutdown::Write).unwrap();
+        let mut received = String::new();
+        client.read_to_string(&mut received).unwrap();
+        assert_eq!(received, "Hello, ");
+        relay.join().unwrap();
+    }
+}
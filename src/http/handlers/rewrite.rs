@@ -0,0 +1,327 @@
+use regex::Regex;
+
+use http::glob;
+use http::headers;
+use http::request::Request;
+use http::response::Response;
+
+/// Pattern matched against the path of a request
+#[derive(Clone, Debug)]
+pub enum Pattern {
+    /// Path starting with a prefix, like `/old/`, capturing the rest
+    /// of the path.
+    Prefix(String),
+
+    /// Glob pattern, like `/blog/**/*.html`, capturing the part of the
+    /// path matched by each wildcard.
+    Glob(String),
+
+    /// Regular expression, like `^/posts/(\d+)$`, capturing its groups.
+    Regex(Regex)
+}
+
+impl Pattern {
+    /// Parse a pattern: a regular expression when it starts with `~`,
+    /// a glob pattern when it contains a wildcard, or else a prefix.
+    pub fn parse(s: &str) -> Result<Pattern, String> {
+        if let Some(regex) = s.strip_prefix('~') {
+            match Regex::new(regex) {
+                Ok(regex) => Ok(Pattern::Regex(regex)),
+                Err(e)    => Err(format!("Could not parse regex '{}': {}", regex, e))
+            }
+        } else if s.contains('*') || s.contains('?') {
+            Ok(Pattern::Glob(s.into()))
+        } else {
+            Ok(Pattern::Prefix(s.into()))
+        }
+    }
+
+    /// Get the captures of the pattern in `path`, starting with the
+    /// whole path, or `None` if it does not match.
+    pub fn captures(&self, path: &str) -> Option<Vec<String>> {
+        let captures = match *self {
+            Pattern::Prefix(ref prefix) => {
+                path.strip_prefix(prefix.as_str()).map(|rest| vec![rest.into()])
+            },
+            Pattern::Glob(ref pattern) => {
+                glob::captures(pattern, path)
+            },
+            Pattern::Regex(ref regex) => {
+                regex.captures(path).map(|captures| {
+                    captures.iter().skip(1).map(|capture| {
+                        capture.map_or("".into(), |capture| capture.as_str().into())
+                    }).collect()
+                })
+            }
+        };
+
+        captures.map(|mut captures| {
+            captures.insert(0, path.into());
+            captures
+        })
+    }
+}
+
+/// Action of a rule on the requests it matches
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    /// Change the URI of the request given to the following handlers.
+    Rewrite,
+
+    /// Redirect the client to the target with a status code like `301`.
+    Redirect(u16)
+}
+
+/// Rule rewriting or redirecting the requests matching a pattern
+#[derive(Clone, Debug)]
+pub struct RewriteRule {
+    pub action: Action,
+    pub pattern: Pattern,
+
+    /// Target URI, where `$N` is replaced by the Nth capture of the pattern
+    /// and `$0` by the whole path.
+    pub target: String,
+
+    /// Glob pattern of the hosts the rule is limited to, like `*.example.com`.
+    pub host: Option<String>,
+
+    /// Methods the rule is limited to, or any method when empty.
+    pub methods: Vec<String>
+}
+
+impl RewriteRule {
+    pub fn new(action: Action, pattern: &str, target: &str) -> Result<RewriteRule, String> {
+        Ok(RewriteRule {
+            action,
+            pattern: Pattern::parse(pattern)?,
+            target: target.into(),
+            host: None,
+            methods: Vec::new()
+        })
+    }
+
+    /// Parse a rule: an action, `rewrite` or a redirect status code,
+    /// a pattern and a target, followed by optional conditions on the
+    /// host and the method, like `301 /old/ /new/$1 host=example.com
+    /// method=GET,HEAD`.
+    pub fn parse(line: &str) -> Result<RewriteRule, String> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 3 {
+            return Err(format!("Could not parse rule '{}'", line));
+        }
+
+        let action = match fields[0] {
+            "rewrite"                     => Action::Rewrite,
+            "301" | "302" | "307" | "308" => Action::Redirect(fields[0].parse().unwrap()),
+            _ => return Err(format!("Could not parse rule action '{}'", fields[0]))
+        };
+        let mut rule = RewriteRule::new(action, fields[1], fields[2])?;
+
+        for condition in &fields[3..] {
+            let mut parts = condition.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some("host"), Some(host)) => {
+                    rule.host = Some(host.to_lowercase());
+                },
+                (Some("method"), Some(methods)) => {
+                    rule.methods = methods.split(',').map(|method| method.to_uppercase()).collect();
+                },
+                _ => return Err(format!("Could not parse rule condition '{}'", condition))
+            }
+        }
+
+        Ok(rule)
+    }
+
+    /// Get the target of the rule for `req`, with the query string of the
+    /// request unless the target has its own, or `None` if the rule does not
+    /// apply to the request.
+    pub fn apply(&self, req: &Request) -> Option<String> {
+        if !self.methods.is_empty() && !self.methods.contains(&req.method) {
+            return None;
+        }
+        if let Some(ref pattern) = self.host {
+            let host = req.headers.get("host").map_or("".into(), |host| headers::host_name(host));
+            if !glob::matches(pattern, &host) {
+                return None;
+            }
+        }
+
+        let mut parts = req.uri.splitn(2, '?');
+        let path = parts.next().unwrap();
+        let query = parts.next();
+        let captures = self.pattern.captures(path)?;
+
+        let mut target = substitute(&self.target, &captures);
+        if let Some(query) = query {
+            if !target.contains('?') {
+                target.push('?');
+                target.push_str(query);
+            }
+        }
+
+        Some(target)
+    }
+}
+
+/// Parse the rules of a configuration file, one per line, ignoring empty
+/// lines and comments starting with `#`.
+pub fn parse_rules(text: &str) -> Result<Vec<RewriteRule>, String> {
+    let mut rules = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match RewriteRule::parse(line) {
+            Ok(rule) => rules.push(rule),
+            Err(e)   => return Err(format!("{} (line {})", e, i + 1))
+        }
+    }
+
+    Ok(rules)
+}
+
+/// Replace `$N` in `target` by the Nth capture.
+fn substitute(target: &str, captures: &[String]) -> String {
+    let mut result = String::new();
+    let mut chars = target.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            result.push(c);
+            continue;
+        }
+
+        let mut digits = String::new();
+        while let Some(&d) = chars.peek() {
+            if !d.is_ascii_digit() {
+                break;
+            }
+            digits.push(d);
+            chars.next();
+        }
+        match digits.parse::<usize>() {
+            Ok(i)  => result.push_str(captures.get(i).map_or("", |capture| capture.as_str())),
+            Err(_) => result.push(c)
+        }
+    }
+
+    result
+}
+
+/// Rewrite or redirect requests with the first rule of
+/// `Server::rewrite_rules` applying to them.
+pub fn handler(req: Request, mut res: Response) -> Response {
+    if res.is_ended() {
+        return res;
+    }
+
+    let server = res.server.clone();
+    let rule = server.rewrite_rules.iter().find_map(|rule| {
+        rule.apply(&req).map(|target| (rule, target))
+    });
+    let (rule, target) = match rule {
+        Some(rule) => rule,
+        None       => return res
+    };
+
+    match rule.action {
+        Action::Rewrite => {
+            let mut req = req;
+            if req.original_uri.is_none() {
+                req.original_uri = Some(req.uri.clone());
+            }
+            req.uri = target;
+            res.update_request(req);
+        },
+        Action::Redirect(status_code) => {
+            res.set_status(status_code);
+            res.headers.set("location", &target);
+            res.end();
+        }
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use http::server::Server;
+
+    #[test]
+    fn test_pattern() {
+        let pattern = Pattern::parse("/old/").unwrap();
+        assert_eq!(pattern.captures("/old/a/b"), Some(vec!["/old/a/b".into(), "a/b".into()]));
+        assert_eq!(pattern.captures("/new/a/b"), None);
+
+        let pattern = Pattern::parse("/blog/*.html").unwrap();
+        assert_eq!(pattern.captures("/blog/hello.html"), Some(vec!["/blog/hello.html".into(), "hello".into()]));
+
+        let pattern = Pattern::parse("~^/posts/(\\d+)(/edit)?$").unwrap();
+        assert_eq!(pattern.captures("/posts/42"), Some(vec!["/posts/42".into(), "42".into(), "".into()]));
+        assert_eq!(pattern.captures("/posts/hello"), None);
+
+        assert!(Pattern::parse("~^/posts/(").is_err());
+    }
+
+    #[test]
+    fn test_substitute() {
+        let captures = vec!["/a/b".into(), "a".into(), "b".into()];
+        assert_eq!(substitute("/$2/$1", &captures), "/b/a");
+        assert_eq!(substitute("/x$0", &captures), "/x/a/b");
+        assert_eq!(substitute("/$3/$", &captures), "//$");
+    }
+
+    #[test]
+    fn test_parse_rules() {
+        let text = "# Old URLs\n\nrewrite /old/ /new/$1\n301 /blog/*.html /posts/$1 host=example.com method=get,head\n";
+        let rules = parse_rules(text).unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[1].action, Action::Redirect(301));
+        assert_eq!(rules[1].host, Some("example.com".into()));
+        assert_eq!(rules[1].methods, vec!["GET".to_string(), "HEAD".to_string()]);
+
+        assert!(parse_rules("303 /a /b").is_err());
+        assert!(parse_rules("rewrite /a").is_err());
+        assert!(parse_rules("rewrite /a /b port=80").is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let rule = RewriteRule::parse("301 /blog/*.html /posts/$1 host=*.example.com method=GET").unwrap();
+
+        let req = Request::new("GET", "www.example.com:8080", "/blog/hello.html?page=2");
+        assert_eq!(rule.apply(&req), Some("/posts/hello?page=2".into()));
+
+        let req = Request::new("POST", "www.example.com", "/blog/hello.html");
+        assert_eq!(rule.apply(&req), None);
+
+        let req = Request::new("GET", "example.org", "/blog/hello.html");
+        assert_eq!(rule.apply(&req), None);
+
+        let rule = RewriteRule::parse("rewrite /old/ /new/$1 host=[::1]").unwrap();
+        let req = Request::new("GET", "[::1]:8080", "/old/index.html");
+        assert_eq!(rule.apply(&req), Some("/new/index.html".into()));
+    }
+
+    #[test]
+    fn test_handler() {
+        let mut server = Server::new();
+        server.rewrite_rules = parse_rules("rewrite /old/ /new/$1\n308 /moved https://example.org$0").unwrap();
+
+        let req = Request::new("GET", "example.com", "/old/index.html");
+        let mut res = handler(req, Response::new(server.clone()));
+        assert!(!res.is_ended());
+        let req = res.take_updated_request().unwrap();
+        assert_eq!(req.uri, "/new/index.html");
+        assert_eq!(req.original_uri, Some("/old/index.html".into()));
+
+        let req = Request::new("GET", "example.com", "/moved");
+        let res = handler(req, Response::new(server));
+        assert!(res.is_ended());
+        assert_eq!(res.status_code, 308);
+        assert_eq!(res.headers.get("location"), Some(&"https://example.org/moved".into()));
+    }
+}
@@ -1,13 +1,95 @@
-use std::fs::File;
-use std::io::prelude::*;
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
 use std::str;
+use time;
 
+use http::file_cache::CachedFile;
+use http::glob;
+use http::headers;
 use http::request::Request;
 use http::response::Response;
+use http::server::Server;
+
+/// Content codings of the precompressed siblings of a file, with their
+/// extensions, in order of preference.
+const PRECOMPRESSED: [(&str, &str); 2] = [("br", "br"), ("gzip", "gz")];
+
+/// Caching policy of the static files matching a pattern
+#[derive(Clone, Debug, PartialEq)]
+pub struct CacheRule {
+    /// Glob pattern matched against the path of the file from the root
+    /// when it starts with `/`, like `/assets/**`, or else against its
+    /// content type, like `text/html` or `image/*`.
+    pub pattern: String,
+
+    /// Value of the `Cache-Control` header, like `max-age=31536000, immutable`
+    /// or `no-cache`. An `Expires` header is added for a `max-age` directive.
+    pub cache_control: String
+}
+
+impl CacheRule {
+    pub fn new(pattern: &str, cache_control: &str) -> CacheRule {
+        CacheRule {
+            pattern: pattern.into(),
+            cache_control: cache_control.into()
+        }
+    }
+
+    /// Return `true` if the rule applies to the file at `path`
+    /// (from the root) of type `content_type`.
+    pub fn matches(&self, path: &str, content_type: Option<&str>) -> bool {
+        if self.pattern.starts_with('/') {
+            glob::matches(&self.pattern, path)
+        } else if let Some(content_type) = content_type {
+            let media_type = content_type.split(';').next().unwrap().trim();
+            glob::matches(&self.pattern, media_type)
+        } else {
+            false
+        }
+    }
+
+    /// Get the value of the `max-age` directive in seconds.
+    pub fn max_age(&self) -> Option<i64> {
+        self.cache_control.split(',')
+            .map(|directive| directive.trim())
+            .find(|directive| directive.starts_with("max-age="))
+            .and_then(|directive| directive["max-age=".len()..].parse().ok())
+    }
+}
+
+/// Policy for the static files reached through symbolic links
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Symlinks {
+    /// Follow all symbolic links.
+    Follow,
+
+    /// Follow symbolic links only to files inside the root.
+    WithinRoot,
+
+    /// Never follow symbolic links below the root.
+    Deny
+}
+
+impl Symlinks {
+    /// Parse a symbolic links policy: `follow`, `within-root` or `deny`.
+    pub fn parse(s: &str) -> Result<Symlinks, String> {
+        match s {
+            "follow"      => Ok(Symlinks::Follow),
+            "within-root" => Ok(Symlinks::WithinRoot),
+            "deny"        => Ok(Symlinks::Deny),
+            _             => Err(format!("Could not parse symlinks policy '{}'", s))
+        }
+    }
+}
 
 /// Server static files.
 pub fn handler(req: Request, mut res: Response) -> Response {
+    // Skip requests already handled by a previous handler
+    if res.is_ended() {
+        return res;
+    }
+
     // FIXME: use handler config instead of server config
     let server = res.server.clone();
 
@@ -16,7 +98,7 @@ pub fn handler(req: Request, mut res: Response) -> Response {
     if server.allow_trace {
         methods.push("TRACE");
     }
-    if let None = methods.iter().find(|&&method| method == req.method) {
+    if !methods.iter().any(|&method| method == req.method) {
         res.status_code = 501;
         res.status_message = "Not Implemented".into();
         res.end();
@@ -40,8 +122,25 @@ pub fn handler(req: Request, mut res: Response) -> Response {
     }
 
     // Build local file path from URI
-    let req_path = String::from(server.root_path) + &req.canonicalized_uri();
-    let mut path = PathBuf::from(&req_path);
+    let mut path = if server.try_files.is_empty() {
+        PathBuf::from(server.root_path.clone() + &req.canonicalized_uri())
+    } else {
+        match try_files(&server, &req.canonicalized_uri()) {
+            Ok(path)         => path,
+            Err(status_code) => {
+                res.set_status(status_code);
+                res.end();
+                return res;
+            }
+        }
+    };
+
+    // Check access policies before revealing if a directory exists
+    if is_denied(&server, &path) {
+        res.set_status(server.deny_status);
+        res.end();
+        return res;
+    }
 
     if path.is_dir() {
         // Trailing slash redirect
@@ -55,11 +154,13 @@ pub fn handler(req: Request, mut res: Response) -> Response {
         }
 
         // Directory index file
-        for index in &server.directory_indexes {
-            if path.join(index).is_file() {
-                path.push(index);
-                break;
+        if let Some(index) = directory_index(&server, &path) {
+            if is_denied(&server, &index) {
+                res.set_status(server.deny_status);
+                res.end();
+                return res;
             }
+            path = index;
         }
     }
 
@@ -71,11 +172,76 @@ pub fn handler(req: Request, mut res: Response) -> Response {
         }
     }
 
-    // Read file
-    let mut body = vec![];
-    if let Err(_) = read_file(path.to_str().unwrap(), &mut body) {
-        res.status_code = 404;
-        res.status_message = "Not Found".into();
+    // The cache rules match the requested file rather than its
+    // precompressed sibling.
+    let file_path = root_relative_path(&server, &path);
+
+    // Serve a precompressed sibling of the file, like `app.js.gz` for
+    // `app.js`, if the client accepts its content coding.
+    if server.serve_precompressed && path.is_file() {
+        let codings: Vec<&str> = PRECOMPRESSED.iter()
+            .filter(|&&(_, ext)| sibling_path(&path, ext).is_file())
+            .filter(|&&(_, ext)| !is_denied(&server, &sibling_path(&path, ext)))
+            .map(|&(coding, _)| coding)
+            .collect();
+
+        if !codings.is_empty() {
+            res.headers.set("vary", "Accept-Encoding");
+
+            let accept_encoding = req.headers.get("accept-encoding").cloned().unwrap_or_default();
+            if let Some(coding) = choose_coding(&accept_encoding, &codings) {
+                let &(_, ext) = PRECOMPRESSED.iter().find(|&&(c, _)| c == coding).unwrap();
+                path = sibling_path(&path, ext);
+                res.headers.set("content-encoding", coding);
+            }
+        }
+    }
+
+    // Read file from the cache if enabled and the file is small enough,
+    // or else open it to be streamed to the client.
+    let file = match fs::metadata(&path) {
+        Ok(ref metadata) if metadata.is_file() => {
+            match server.file_cache {
+                // The content of the file is not needed to answer a HEAD
+                // request, nor to be cached for it.
+                _ if req.method == "HEAD" => Ok((CachedFile::from_metadata(metadata), None)),
+                Some(ref cache) if metadata.len() <= cache.max_entry_size as u64 => {
+                    cache.get(&path).map(|file| (file, None))
+                },
+                _ => File::open(&path).map(|f| (CachedFile::from_metadata(metadata), Some(f)))
+            }
+        },
+        Ok(_)  => Err(io::Error::other("Not a file")),
+        Err(e) => Err(e)
+    };
+    let (file, opened_file) = match file {
+        Ok(file) => file,
+        Err(_)   => {
+            res.status_code = 404;
+            res.status_message = "Not Found".into();
+            res.end();
+            return res;
+        }
+    };
+    res.headers.set("etag", &file.etag);
+    res.headers.set("last-modified", &file.last_modified);
+
+    // Set caching headers from the first matching rule
+    let content_type = res.headers.get("content-type").cloned();
+    let rule = server.cache_rules.iter().find(|rule| {
+        rule.matches(&file_path, content_type.as_deref())
+    });
+    if let Some(rule) = rule {
+        res.headers.set("cache-control", &rule.cache_control);
+        if let Some(max_age) = rule.max_age() {
+            let expires = time::now_utc() + time::Duration::seconds(max_age);
+            res.headers.set("expires", &headers::http_date(&expires));
+        }
+    }
+
+    if is_not_modified(&req, &file) {
+        res.status_code = 304;
+        res.status_message = "Not Modified".into();
         res.end();
         return res;
     }
@@ -87,25 +253,385 @@ pub fn handler(req: Request, mut res: Response) -> Response {
         // (RFC 2616 9.4)
 
         // This is done automatically by `res.send(&body)`;
-        let content_length = body.len().to_string();
+        let content_length = file.size.to_string();
         res.headers.set("content-length", &content_length);
 
         res.end();
-    } else { // GET method
-        res.send(&body);
+    } else if let Some(opened_file) = opened_file { // GET method
+        res.headers.set("content-length", &file.size.to_string());
+        res.send_file(opened_file);
+    } else {
+        res.send(&file.body);
     }
 
     res
 }
 
-fn read_file(path: &str, buf: &mut Vec<u8>) -> Result<(), String> {
-    match File::open(path) {
-        Err(_) => return Err("Could not parse request line".into()),
-        Ok(mut file) => {
-            if let Err(_) = file.read_to_end(buf) {
-                return Err("Could not parse request line".into())
+/// Resolve the path `uri` to a file with the candidates of `Server::try_files`,
+/// or return the status code of the response if none of them exists.
+fn try_files(server: &Server, uri: &str) -> Result<PathBuf, u16> {
+    let (fallback, candidates) = match server.try_files.split_last() {
+        Some(try_files) => try_files,
+        None            => return Err(404)
+    };
+
+    for candidate in candidates {
+        if let Some(path) = resolve(server, &candidate.replace("$uri", uri)) {
+            return Ok(path);
+        }
+    }
+
+    if let Some(status_code) = fallback.strip_prefix('=') {
+        return Err(status_code.parse().unwrap_or(404));
+    }
+    resolve(server, &fallback.replace("$uri", uri)).ok_or(404)
+}
+
+/// Get the file at `uri` from the root, or the index file of the directory
+/// when `uri` ends with `/`.
+fn resolve(server: &Server, uri: &str) -> Option<PathBuf> {
+    let path = PathBuf::from(server.root_path.clone() + uri);
+    if uri.ends_with('/') {
+        directory_index(server, &path)
+    } else if path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+fn directory_index(server: &Server, dir: &Path) -> Option<PathBuf> {
+    server.directory_indexes.iter()
+        .map(|index| dir.join(index))
+        .find(|path| path.is_file())
+}
+
+/// Get the path of the file at `path` from the root, like `/assets/app.js`.
+fn root_relative_path(server: &Server, path: &Path) -> String {
+    let file_path = match path.strip_prefix(&server.root_path) {
+        Ok(file_path) => PathBuf::from("/").join(file_path),
+        Err(_)        => path.to_path_buf()
+    };
+    file_path.to_str().unwrap().into()
+}
+
+/// Return `true` if the access policies of the server deny access
+/// to the file at `path`.
+fn is_denied(server: &Server, path: &Path) -> bool {
+    let file_path = root_relative_path(server, path);
+
+    if server.deny_hidden {
+        let hidden = file_path.split('/').any(|name| {
+            name.starts_with('.') && !server.allowed_hidden.iter().any(|allowed| allowed == name)
+        });
+        if hidden {
+            return true;
+        }
+    }
+
+    if server.deny_patterns.iter().any(|pattern| glob::matches(pattern, &file_path)) {
+        return true;
+    }
+
+    !is_symlink_allowed(server, path)
+}
+
+/// Return `true` if the policy of the server allows to reach the file
+/// at `path` through symbolic links, if any.
+fn is_symlink_allowed(server: &Server, path: &Path) -> bool {
+    match server.symlinks {
+        Symlinks::Follow => true,
+        Symlinks::WithinRoot => {
+            // A missing file will not be served anyway
+            match (fs::canonicalize(&server.root_path), fs::canonicalize(path)) {
+                (Ok(root), Ok(path)) => path.starts_with(root),
+                (Ok(_), Err(_))      => true,
+                (Err(_), _)          => false
             }
+        },
+        Symlinks::Deny => {
+            let mut current = PathBuf::from(&server.root_path);
+            let relative = match path.strip_prefix(&server.root_path) {
+                Ok(relative) => relative,
+                Err(_)       => return false
+            };
+            relative.components().all(|component| {
+                current.push(component);
+                match fs::symlink_metadata(&current) {
+                    Ok(metadata) => !metadata.file_type().is_symlink(),
+                    Err(_)       => true
+                }
+            })
         }
     }
-    Ok(())
+}
+
+fn sibling_path(path: &Path, extension: &str) -> PathBuf {
+    let mut sibling = path.to_path_buf().into_os_string();
+    sibling.push(".");
+    sibling.push(extension);
+    PathBuf::from(sibling)
+}
+
+/// Choose among the available content `codings` the one preferred
+/// by the client in the value of an `Accept-Encoding` header.
+fn choose_coding<'a>(accept_encoding: &str, codings: &[&'a str]) -> Option<&'a str> {
+    let accepted = headers::quality_values(accept_encoding);
+
+    let mut best = (None, 0.0);
+    for &coding in codings {
+        let q = accepted.iter().find(|&(c, _)| c == coding)
+            .or_else(|| accepted.iter().find(|&(c, _)| c == "*"))
+            .map_or(0.0, |&(_, q)| q);
+        if q > best.1 {
+            best = (Some(coding), q);
+        }
+    }
+
+    best.0
+}
+
+/// Return `true` if the representation of `file` known by the client,
+/// according to the conditional headers of the request, is still valid.
+fn is_not_modified(req: &Request, file: &CachedFile) -> bool {
+    if let Some(if_none_match) = req.headers.get("if-none-match") {
+        // Weak comparison of entity tags
+        let etag = file.etag.trim_start_matches("W/");
+        return if_none_match.split(',').map(|tag| tag.trim()).any(|tag| {
+            tag == "*" || tag.trim_start_matches("W/") == etag
+        });
+    }
+
+    if let Some(if_modified_since) = req.headers.get("if-modified-since") {
+        let since = match time::strptime(if_modified_since, "%a, %d %b %Y %T GMT") {
+            Ok(tm) => tm.to_timespec().sec,
+            Err(_) => return false
+        };
+        let modified = match time::strptime(&file.last_modified, "%a, %d %b %Y %T GMT") {
+            Ok(tm) => tm.to_timespec().sec,
+            Err(_) => return false
+        };
+        return modified <= since;
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    use http::file_cache::FileCache;
+
+    #[test]
+    fn test_try_files() {
+        let root = env::temp_dir().join(format!("simpleton-try-files-{}", time::precise_time_ns()));
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("index.html"), "index").unwrap();
+        fs::write(root.join("about.html"), "about").unwrap();
+        fs::write(root.join("docs/index.html"), "docs").unwrap();
+
+        let mut server = Server::new();
+        server.root_path = root.to_str().unwrap().into();
+        server.try_files = vec!["$uri".into(), "$uri.html".into(), "$uri/".into(), "=404".into()];
+
+        assert_eq!(try_files(&server, "/about.html"), Ok(root.join("about.html")));
+        assert_eq!(try_files(&server, "/about"), Ok(root.join("about.html")));
+        assert_eq!(try_files(&server, "/docs"), Ok(root.join("docs/index.html")));
+        assert_eq!(try_files(&server, "/"), Ok(root.join("index.html")));
+        assert_eq!(try_files(&server, "/app/settings"), Err(404));
+
+        // Single-page application
+        server.try_files = vec!["$uri".into(), "/index.html".into()];
+        assert_eq!(try_files(&server, "/app/settings"), Ok(root.join("index.html")));
+
+        server.try_files = vec!["$uri".into(), "=410".into()];
+        assert_eq!(try_files(&server, "/old"), Err(410));
+
+        let req = Request::new("GET", "example.com", "/old?page=2");
+        let res = handler(req, Response::new(server));
+        assert_eq!(res.status_code, 410);
+        assert_eq!(res.status_message, "Gone");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_is_denied() {
+        let root = env::temp_dir().join(format!("simpleton-denied-{}", time::precise_time_ns()));
+        let outside = env::temp_dir().join(format!("simpleton-outside-{}", time::precise_time_ns()));
+        fs::create_dir_all(root.join(".well-known")).unwrap();
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(&outside, "secret").unwrap();
+        fs::write(root.join(".env"), "SECRET=1").unwrap();
+        fs::write(root.join("index.html"), "index").unwrap();
+        ::std::os::unix::fs::symlink(&outside, root.join("outside.txt")).unwrap();
+        ::std::os::unix::fs::symlink(root.join("index.html"), root.join("docs/index.html")).unwrap();
+
+        let mut server = Server::new();
+        server.root_path = root.to_str().unwrap().into();
+        assert!(!is_denied(&server, &root.join(".env")));
+        assert!(!is_denied(&server, &root.join("outside.txt")));
+
+        server.deny_hidden = true;
+        assert!(is_denied(&server, &root.join(".env")));
+        assert!(is_denied(&server, &root.join(".git/config")));
+        assert!(!is_denied(&server, &root.join(".well-known/security.txt")));
+
+        server.deny_patterns = vec!["/**/*.bak".into()];
+        assert!(is_denied(&server, &root.join("docs/index.html.bak")));
+        assert!(!is_denied(&server, &root.join("docs/index.html")));
+
+        server.symlinks = Symlinks::WithinRoot;
+        assert!(is_denied(&server, &root.join("outside.txt")));
+        assert!(!is_denied(&server, &root.join("docs/index.html")));
+
+        server.symlinks = Symlinks::Deny;
+        assert!(is_denied(&server, &root.join("docs/index.html")));
+        assert!(!is_denied(&server, &root.join("index.html")));
+
+        server.deny_status = 403;
+        let req = Request::new("GET", "example.com", "/.env");
+        let res = handler(req, Response::new(server));
+        assert_eq!(res.status_code, 403);
+
+        fs::remove_dir_all(&root).unwrap();
+        fs::remove_file(&outside).unwrap();
+    }
+
+    #[test]
+    fn test_sibling_path() {
+        let path = PathBuf::from("/var/www/app.js");
+        assert_eq!(sibling_path(&path, "gz"), PathBuf::from("/var/www/app.js.gz"));
+    }
+
+    #[test]
+    fn test_cache_rule() {
+        let rule = CacheRule::new("/assets/**", "max-age=31536000, immutable");
+        assert!(rule.matches("/assets/app.3f2a.js", Some("application/javascript")));
+        assert!(!rule.matches("/index.html", Some("text/html")));
+        assert_eq!(rule.max_age(), Some(31536000));
+
+        let rule = CacheRule::new("text/html", "no-cache");
+        assert!(rule.matches("/index.html", Some("text/html; charset=utf-8")));
+        assert!(!rule.matches("/index.html", None));
+        assert_eq!(rule.max_age(), None);
+
+        let rule = CacheRule::new("image/*", "public, max-age=3600");
+        assert!(rule.matches("/logo.png", Some("image/png")));
+        assert_eq!(rule.max_age(), Some(3600));
+    }
+
+    #[test]
+    fn test_precompressed_cache_rule() {
+        let root = env::temp_dir().join(format!("simpleton-precompressed-{}", time::precise_time_ns()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("app.js"), "app").unwrap();
+        fs::write(root.join("app.js.gz"), "gzip").unwrap();
+
+        let mut server = Server::new();
+        server.root_path = root.to_str().unwrap().into();
+        server.serve_precompressed = true;
+        server.cache_rules = vec![CacheRule::new("/**/*.js", "max-age=3600")];
+
+        let mut req = Request::new("GET", "example.com", "/app.js");
+        req.headers.set("accept-encoding", "gzip");
+        let res = handler(req, Response::new(server));
+        assert_eq!(res.headers.get("content-encoding"), Some(&"gzip".to_string()));
+        assert_eq!(res.headers.get("cache-control"), Some(&"max-age=3600".to_string()));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_precompressed_denied() {
+        let root = env::temp_dir().join(format!("simpleton-precompressed-denied-{}", time::precise_time_ns()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("app.js"), "app").unwrap();
+        fs::write(root.join("app.js.gz"), "gzip").unwrap();
+
+        let mut server = Server::new();
+        server.root_path = root.to_str().unwrap().into();
+        server.serve_precompressed = true;
+        server.deny_patterns = vec!["/**/*.gz".into()];
+
+        let mut req = Request::new("GET", "example.com", "/app.js");
+        req.headers.set("accept-encoding", "gzip");
+        let res = handler(req, Response::new(server));
+        assert!(!res.headers.contains_key("content-encoding"));
+        assert_eq!(res.headers.get("content-length"), Some(&"3".to_string()));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_head_not_cached() {
+        let root = env::temp_dir().join(format!("simpleton-head-{}", time::precise_time_ns()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("index.html"), "Hello, World!").unwrap();
+
+        let cache = FileCache::new(1024, 1024);
+        let mut server = Server::new();
+        server.root_path = root.to_str().unwrap().into();
+        server.file_cache = Some(cache.clone());
+
+        let req = Request::new("HEAD", "example.com", "/index.html");
+        let res = handler(req, Response::new(server.clone()));
+        assert_eq!(res.headers.get("content-length"), Some(&"13".to_string()));
+        assert_eq!(cache.usage(), (0, 0));
+
+        let req = Request::new("GET", "example.com", "/index.html");
+        let res = handler(req, Response::new(server));
+        assert_eq!(res.body(), b"Hello, World!");
+        assert_eq!(cache.usage(), (1, 13));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_is_not_modified() {
+        let file = CachedFile {
+            body: Default::default(),
+            size: 500,
+            modified: ::std::time::UNIX_EPOCH,
+            etag: "\"1f4-5f2b1c3a\"".into(),
+            last_modified: "Wed, 05 Aug 2020 20:50:02 GMT".into()
+        };
+
+        let req = Request::new("GET", "example.com", "/");
+        assert!(!is_not_modified(&req, &file));
+
+        let mut req = Request::new("GET", "example.com", "/");
+        req.headers.set("if-none-match", "\"abc\", W/\"1f4-5f2b1c3a\"");
+        assert!(is_not_modified(&req, &file));
+
+        let mut req = Request::new("GET", "example.com", "/");
+        req.headers.set("if-none-match", "\"abc\"");
+        req.headers.set("if-modified-since", "Wed, 05 Aug 2020 20:50:02 GMT");
+        assert!(!is_not_modified(&req, &file));
+
+        let mut req = Request::new("GET", "example.com", "/");
+        req.headers.set("if-modified-since", "Wed, 05 Aug 2020 20:50:02 GMT");
+        assert!(is_not_modified(&req, &file));
+
+        let mut req = Request::new("GET", "example.com", "/");
+        req.headers.set("if-modified-since", "Wed, 05 Aug 2020 20:50:01 GMT");
+        assert!(!is_not_modified(&req, &file));
+    }
+
+    #[test]
+    fn test_choose_coding() {
+        let codings = vec!["br", "gzip"];
+
+        assert_eq!(choose_coding("", &codings), None);
+        assert_eq!(choose_coding("gzip, deflate, br", &codings), Some("br"));
+        assert_eq!(choose_coding("gzip, deflate", &codings), Some("gzip"));
+        assert_eq!(choose_coding("br;q=0.5, gzip", &codings), Some("gzip"));
+        assert_eq!(choose_coding("*", &codings), Some("br"));
+        assert_eq!(choose_coding("br;q=0, *", &codings), Some("gzip"));
+        assert_eq!(choose_coding("gzip", &["br"]), None);
+    }
 }
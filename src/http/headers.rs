@@ -4,11 +4,14 @@
  * non-deterministic.
  */
 use std::collections::BTreeMap;
-use std::collections::btree_map::IntoIter;
+use std::vec::IntoIter;
+use time::{self, Tm};
 
 #[derive(Clone)]
 pub struct Headers {
-    headers: BTreeMap<String, String>
+    /// Values of the fields, more than one only for the fields added with
+    /// `Headers::add`.
+    headers: BTreeMap<String, Vec<String>>
 }
 
 impl Headers {
@@ -19,11 +22,29 @@ impl Headers {
     }
 
     pub fn get(&self, name: &str) -> Option<&String> {
-        self.headers.get(&name.to_lowercase())
+        self.headers.get(&name.to_lowercase()).and_then(|values| values.first())
+    }
+
+    /// Get the values of all the fields `name`.
+    pub fn get_all(&self, name: &str) -> &[String] {
+        self.headers.get(&name.to_lowercase()).map_or(&[], |values| values.as_slice())
     }
 
     pub fn set(&mut self, name: &str, value: &str) {
-        self.headers.insert(name.to_lowercase(), value.into());
+        self.headers.insert(name.to_lowercase(), vec![value.into()]);
+    }
+
+    /// Add a field `name` after the existing ones instead of replacing them,
+    /// for the fields whose values cannot be combined in a comma-separated
+    /// list, like `Set-Cookie`.
+    ///
+    /// (RFC 7230 3.2.2)
+    pub fn add(&mut self, name: &str, value: &str) {
+        self.headers.entry(name.to_lowercase()).or_default().push(value.into());
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        self.headers.remove(&name.to_lowercase()).and_then(|values| values.into_iter().next())
     }
 
     pub fn contains_key(&self, name: &str) -> bool {
@@ -31,12 +52,79 @@ impl Headers {
     }
 }
 
-impl<'a> IntoIterator for &'a Headers {
+/// Format `tm` as an HTTP-date, like `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn http_date(tm: &Tm) -> String {
+    time::strftime("%a, %d %b %Y %T GMT", &tm.to_utc()).unwrap()
+}
+
+/// Parse a header field value made of a list of elements with optional
+/// quality values, like `Accept` or `Accept-Encoding`, and sort them by
+/// decreasing preference.
+///
+/// Elements without a `q` parameter have a quality value of `1`.
+pub fn quality_values(value: &str) -> Vec<(String, f32)> {
+    let mut values = vec![];
+
+    for element in value.split(',') {
+        let mut params = element.split(';');
+        let name = match params.next() {
+            None       => continue,
+            Some(name) => name.trim().to_lowercase()
+        };
+        if name.is_empty() {
+            continue;
+        }
+
+        let mut q = 1.0;
+        for param in params {
+            let mut fields = param.splitn(2, '=');
+            if fields.next().map(|s| s.trim()) == Some("q") {
+                q = fields.next().and_then(|s| s.trim().parse().ok()).unwrap_or(0.0);
+                if !(0.0..=1.0).contains(&q) {
+                    q = 0.0;
+                }
+            }
+        }
+        values.push((name, q));
+    }
+
+    // The sort is stable so elements of equal quality keep their order
+    values.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+
+    values
+}
+
+/// Get the lowercase host name of the value of a `Host` header, without
+/// its port, like `example.com` for `Example.com:8080` or `[::1]` for
+/// `[::1]:8080`.
+///
+/// (RFC 7230 5.4)
+pub fn host_name(value: &str) -> String {
+    let host = value.trim().to_lowercase();
+    match host.rfind(':') {
+        Some(i) if !host[i..].contains(']') => host[..i].into(),
+        _                                   => host
+    }
+}
+
+impl Default for Headers {
+    fn default() -> Headers {
+        Headers::new()
+    }
+}
+
+impl IntoIterator for &Headers {
     type Item = (String, String);
-    type IntoIter = IntoIter<String, String>;
+    type IntoIter = IntoIter<(String, String)>;
 
+    /// Iterate over the fields, once for each value of the fields added
+    /// with `Headers::add`.
     fn into_iter(self) -> Self::IntoIter {
-        self.headers.clone().into_iter()
+        let fields: Vec<(String, String)> = self.headers.iter().flat_map(|(name, values)| {
+            values.iter().map(move |value| (name.clone(), value.clone()))
+        }).collect();
+
+        fields.into_iter()
     }
 }
 
@@ -48,11 +136,11 @@ mod tests {
     fn test_set() {
         let mut headers = Headers::new();
 
-        headers.set("content-type".into(), "text/html".into());
+        headers.set("content-type", "text/html");
         assert_eq!(headers.get("content-type"), Some(&"text/html".into()));
 
         // The name of the header is case insensitive
-        headers.set("Content-Type".into(), "text/plain".into());
+        headers.set("Content-Type", "text/plain");
         assert_eq!(headers.get("content-type"), Some(&"text/plain".into()));
     }
 
@@ -62,10 +150,51 @@ mod tests {
 
         assert_eq!(headers.get("not-set"), None);
 
-        headers.set("content-type".into(), "text/html".into());
+        headers.set("content-type", "text/html");
         assert_eq!(headers.get("content-type"), Some(&"text/html".into()));
 
         // The name of the header is case insensitive
         assert_eq!(headers.get("Content-Type"), Some(&"text/html".into()));
     }
+
+    #[test]
+    fn test_add() {
+        let mut headers = Headers::new();
+
+        headers.add("Set-Cookie", "a=1");
+        headers.add("set-cookie", "b=2; Path=/");
+        assert_eq!(headers.get("set-cookie"), Some(&"a=1".into()));
+        assert_eq!(headers.get_all("set-cookie"), ["a=1".to_string(), "b=2; Path=/".to_string()]);
+        let fields: Vec<(String, String)> = (&headers).into_iter().collect();
+        assert_eq!(fields.len(), 2);
+
+        headers.set("set-cookie", "c=3");
+        assert_eq!(headers.get_all("set-cookie"), ["c=3".to_string()]);
+    }
+
+    #[test]
+    fn test_http_date() {
+        let tm = time::at_utc(time::Timespec::new(784111777, 0));
+        assert_eq!(http_date(&tm), "Sun, 06 Nov 1994 08:49:37 GMT");
+    }
+
+    #[test]
+    fn test_quality_values() {
+        let values = quality_values("text/html;q=0.5, application/json, */*;q=0.1");
+        assert_eq!(values, vec![
+            ("application/json".into(), 1.0),
+            ("text/html".into(), 0.5),
+            ("*/*".into(), 0.1)
+        ]);
+
+        assert!(quality_values("").is_empty());
+    }
+
+    #[test]
+    fn test_host_name() {
+        assert_eq!(host_name("Example.com:8080"), "example.com");
+        assert_eq!(host_name("example.com"), "example.com");
+        assert_eq!(host_name("[::1]:8080"), "[::1]");
+        assert_eq!(host_name("[::1]"), "[::1]");
+    }
 }
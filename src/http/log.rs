@@ -0,0 +1,334 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter};
+use std::io::prelude::*;
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+use libc;
+use time;
+
+/// Incremented every time the log files must be reopened.
+static REOPEN_GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// Interval between two flushes of the buffered log files.
+const FLUSH_INTERVAL: u64 = 1;
+
+/// Rotation policy of a log file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rotation {
+    /// The file is never rotated.
+    Never,
+
+    /// The file is rotated before it grows larger than the given
+    /// number of bytes.
+    Size(u64),
+
+    /// The file is rotated at the beginning of each hour.
+    Hourly,
+
+    /// The file is rotated at the beginning of each day.
+    Daily
+}
+
+impl Rotation {
+    /// Parse a rotation policy: `never`, `hourly`, `daily` or a size in
+    /// bytes with an optional `K`, `M` or `G` suffix (for example `10M`).
+    pub fn parse(s: &str) -> Result<Rotation, String> {
+        match s {
+            "never"  => return Ok(Rotation::Never),
+            "hourly" => return Ok(Rotation::Hourly),
+            "daily"  => return Ok(Rotation::Daily),
+            _        => {}
+        }
+
+        let (digits, multiplier) = match s.chars().last() {
+            Some('K') | Some('k') => (&s[..s.len() - 1], 1 << 10),
+            Some('M') | Some('m') => (&s[..s.len() - 1], 1 << 20),
+            Some('G') | Some('g') => (&s[..s.len() - 1], 1 << 30),
+            _                     => (s, 1)
+        };
+        match digits.parse::<u64>().ok().and_then(|n| n.checked_mul(multiplier)) {
+            Some(n) if n > 0 => Ok(Rotation::Size(n)),
+            _                => Err(format!("Could not parse log rotation '{}'", s))
+        }
+    }
+
+    /// Name of the current time period, changing when the file must be
+    /// rotated for time based policies.
+    fn period(&self) -> String {
+        let format = match *self {
+            Rotation::Hourly => "%Y%m%d%H",
+            Rotation::Daily  => "%Y%m%d",
+            _                => return String::new()
+        };
+        time::strftime(format, &time::now()).unwrap()
+    }
+}
+
+/// Destination of the lines of a log, shared between the threads
+/// of the server.
+#[derive(Clone)]
+pub struct LogSink {
+    target: Arc<Mutex<Target>>
+}
+
+enum Target {
+    Stdout,
+    Stderr,
+    File(LogFile)
+}
+
+struct LogFile {
+    path: String,
+    writer: BufWriter<File>,
+    size: u64,
+    rotation: Rotation,
+    period: String,
+    retention: usize,
+    generation: usize
+}
+
+impl LogSink {
+    /// Create a sink writing lines to the standard output.
+    pub fn stdout() -> LogSink {
+        LogSink { target: Arc::new(Mutex::new(Target::Stdout)) }
+    }
+
+    /// Create a sink writing lines to the standard error.
+    pub fn stderr() -> LogSink {
+        LogSink { target: Arc::new(Mutex::new(Target::Stderr)) }
+    }
+
+    /// Create a sink appending lines to the file at `path`.
+    ///
+    /// The output is buffered and flushed every second. The file is rotated
+    /// following `rotation`, keeping at most `retention` rotated files
+    /// named `<path>.1` (the most recent) to `<path>.<retention>`.
+    pub fn file(path: &str, rotation: Rotation, retention: usize) -> io::Result<LogSink> {
+        let log_file = LogFile::open(path, rotation, retention)?;
+        let sink = LogSink { target: Arc::new(Mutex::new(Target::File(log_file))) };
+
+        let target = Arc::downgrade(&sink.target);
+        thread::spawn(move || flush_periodically(target));
+
+        Ok(sink)
+    }
+
+    /// Write `line` followed by a newline.
+    pub fn write_line(&self, line: &str) {
+        let mut target = self.target.lock().unwrap();
+        match *target {
+            Target::Stdout => {
+                let _ = writeln!(io::stdout(), "{}", line);
+            },
+            Target::Stderr => {
+                let _ = writeln!(io::stderr(), "{}", line);
+            },
+            Target::File(ref mut log_file) => {
+                log_file.write_line(line);
+            }
+        }
+    }
+
+    /// Write the buffered lines to the file.
+    pub fn flush(&self) {
+        if let Target::File(ref mut log_file) = *self.target.lock().unwrap() {
+            let _ = log_file.writer.flush();
+        }
+    }
+
+    /// Close and open again the file, for example after it was moved
+    /// by an external tool like `logrotate`.
+    pub fn reopen(&self) {
+        if let Target::File(ref mut log_file) = *self.target.lock().unwrap() {
+            let _ = log_file.reopen();
+        }
+    }
+}
+
+impl LogFile {
+    fn open(path: &str, rotation: Rotation, retention: usize) -> io::Result<LogFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+
+        Ok(LogFile {
+            path: path.into(),
+            writer: BufWriter::new(file),
+            size,
+            rotation,
+            period: rotation.period(),
+            retention,
+            generation: REOPEN_GENERATION.load(Ordering::SeqCst)
+        })
+    }
+
+    fn write_line(&mut self, line: &str) {
+        self.check_reopen();
+
+        let len = line.len() as u64 + 1;
+        let must_rotate = match self.rotation {
+            Rotation::Never   => false,
+            Rotation::Size(n) => self.size > 0 && self.size + len > n,
+            _                 => self.period != self.rotation.period()
+        };
+        if must_rotate {
+            let _ = self.rotate();
+        }
+
+        if writeln!(self.writer, "{}", line).is_ok() {
+            self.size += len;
+        }
+    }
+
+    /// Reopen the file if it was requested by a signal.
+    fn check_reopen(&mut self) {
+        let generation = REOPEN_GENERATION.load(Ordering::SeqCst);
+        if self.generation != generation {
+            let _ = self.reopen();
+        }
+    }
+
+    fn reopen(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        *self = LogFile::open(&self.path, self.rotation, self.retention)?;
+        Ok(())
+    }
+
+    /// Shift the rotated files, removing the oldest one, then move
+    /// the current file to `<path>.1` and open a new one.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+
+        if self.retention == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.rotated_path(self.retention));
+            for i in (1..self.retention).rev() {
+                let _ = fs::rename(self.rotated_path(i), self.rotated_path(i + 1));
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+
+        self.reopen()
+    }
+
+    fn rotated_path(&self, i: usize) -> String {
+        format!("{}.{}", self.path, i)
+    }
+}
+
+impl Drop for LogFile {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+/// Flush the file of a sink until it is dropped, reopening it when
+/// requested by a signal even if nothing is written.
+fn flush_periodically(target: Weak<Mutex<Target>>) {
+    loop {
+        thread::sleep(Duration::from_secs(FLUSH_INTERVAL));
+
+        let target = match target.upgrade() {
+            None         => return,
+            Some(target) => target
+        };
+        if let Target::File(ref mut log_file) = *target.lock().unwrap() {
+            log_file.check_reopen();
+            let _ = log_file.writer.flush();
+        };
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn handle_sighup(_: libc::c_int) {
+    REOPEN_GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Reopen every log file when the process receives the `SIGHUP` signal.
+#[cfg(unix)]
+pub fn reopen_on_sighup() {
+    let handler = handle_sighup as extern "C" fn(libc::c_int);
+    unsafe {
+        libc::signal(libc::SIGHUP, handler as libc::sighandler_t);
+    }
+}
+
+/// Reopen every log file when the process receives the `SIGHUP` signal.
+#[cfg(not(unix))]
+pub fn reopen_on_sighup() {
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    fn temp_path(name: &str) -> String {
+        let path = env::temp_dir().join(format!("simpleton-{}-{}", name, time::precise_time_ns()));
+        path.to_str().unwrap().into()
+    }
+
+    #[test]
+    fn test_parse_rotation() {
+        assert_eq!(Rotation::parse("never"), Ok(Rotation::Never));
+        assert_eq!(Rotation::parse("daily"), Ok(Rotation::Daily));
+        assert_eq!(Rotation::parse("512"), Ok(Rotation::Size(512)));
+        assert_eq!(Rotation::parse("10M"), Ok(Rotation::Size(10 << 20)));
+        assert!(Rotation::parse("0").is_err());
+        assert!(Rotation::parse("weekly").is_err());
+        assert!(Rotation::parse("18446744073709551615G").is_err());
+    }
+
+    #[test]
+    fn test_write_line() {
+        let path = temp_path("write");
+        let sink = LogSink::file(&path, Rotation::Never, 0).unwrap();
+
+        sink.write_line("first");
+        sink.clone().write_line("second");
+        sink.flush();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first\nsecond\n");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_rotate_by_size() {
+        let path = temp_path("rotate");
+        let sink = LogSink::file(&path, Rotation::Size(12), 2).unwrap();
+
+        for line in &["line 1", "line 2", "line 3", "line 4"] {
+            sink.write_line(line);
+        }
+        sink.flush();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "line 4\n");
+        assert_eq!(fs::read_to_string(format!("{}.1", path)).unwrap(), "line 3\n");
+        assert_eq!(fs::read_to_string(format!("{}.2", path)).unwrap(), "line 2\n");
+        assert!(fs::metadata(format!("{}.3", path)).is_err());
+
+        for i in 0..3 {
+            let _ = fs::remove_file(if i == 0 { path.clone() } else { format!("{}.{}", path, i) });
+        }
+    }
+
+    #[test]
+    fn test_reopen() {
+        let path = temp_path("reopen");
+        let sink = LogSink::file(&path, Rotation::Never, 0).unwrap();
+
+        sink.write_line("before");
+        sink.flush();
+        fs::rename(&path, format!("{}.old", path)).unwrap();
+        sink.reopen();
+        sink.write_line("after");
+        sink.flush();
+        assert_eq!(fs::read_to_string(format!("{}.old", path)).unwrap(), "before\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "after\n");
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(format!("{}.old", path)).unwrap();
+    }
+}
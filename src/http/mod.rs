@@ -10,6 +10,45 @@ pub mod headers;
 /// HTTP server
 pub mod server;
 
+/// Chunked transfer coding
+pub mod chunked;
+
+/// Zero-copy transmission of files
+pub mod sendfile;
+
+/// Cache of static files
+pub mod file_cache;
+
+/// Compression of response bodies
+pub mod compression;
+
+/// Timings of the processing of requests
+pub mod timings;
+
+/// HTTP server logs
+pub mod log;
+
+/// Glob patterns matching
+pub mod glob;
+
+/// Escaping of strings for HTML and JSON documents
+pub mod escape;
+
+/// CIDR blocks of IP addresses
+pub mod cidr;
+
+/// Client information forwarded by trusted proxies
+pub mod forwarded;
+
+/// Protocol switching and connections taken over by handlers
+pub mod upgrade;
+
+/// Server-Sent Events
+pub mod event_stream;
+
+/// WebSocket protocol
+pub mod websocket;
+
 /// HTTP server handlers
 pub mod handlers;
 
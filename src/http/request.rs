@@ -1,9 +1,32 @@
 use std::fmt;
+use std::io;
 use std::io::prelude::*;
 use std::net::TcpStream;
 use std::path::{Path, PathBuf, Component};
+use std::str;
+use time::{self, Tm};
 
+use http::chunked::ChunkedReader;
 use http::headers::Headers;
+use http::timings::Timings;
+
+/// Form of the request-target in the request line
+///
+/// (RFC 7230 5.3)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TargetForm {
+    /// Absolute path and query, like `/index.html?page=2`.
+    Origin,
+
+    /// Absolute URI, like `http://example.com/index.html`, sent to proxies.
+    Absolute,
+
+    /// Host and port, like `example.com:443`, only used by `CONNECT`.
+    Authority,
+
+    /// `*`, only used by a server-wide `OPTIONS`.
+    Asterisk
+}
 
 /// HTTP request message
 #[derive(Clone)]
@@ -16,6 +39,13 @@ pub struct Request {
     /// the resource upon which to apply the request.
     pub uri: String,
 
+    /// Request-URI received from the client, when `uri` has been rewritten
+    /// by a handler or reduced to the path of an absolute URI.
+    pub original_uri: Option<String>,
+
+    /// Form of the Request-URI received from the client.
+    pub target_form: TargetForm,
+
     /// HTTP Version: `HTTP/<major>.<minor>`.
     pub version: String,
 
@@ -24,7 +54,33 @@ pub struct Request {
     /// the server.
     pub headers: Headers,
 
-    pub ip: String // TODO: replace it by Option<String>
+    /// The message-body of the request, decoded from the chunked transfer
+    /// coding if it was used.
+    pub body: Vec<u8>,
+
+    /// Address of the client, forwarded by the proxy in `peer_ip` when it
+    /// is trusted.
+    pub ip: String, // TODO: replace it by Option<String>
+
+    /// Address of the peer of the connection, the client or a proxy.
+    pub peer_ip: String,
+
+    /// Scheme of the request sent by the client, `http` unless another one
+    /// is forwarded by a trusted proxy.
+    pub scheme: String,
+
+    /// Name of the user authenticated by a handler.
+    pub user: Option<String>,
+
+    /// Scheme of the authentication of `user` in lowercase, like `basic`
+    /// or `digest`.
+    pub auth_scheme: Option<String>,
+
+    /// Time at which the server started to receive the request.
+    pub start_time: Tm,
+
+    /// Timestamps of the processing of the request by the server.
+    pub timings: Timings
 }
 
 impl Request {
@@ -35,18 +91,28 @@ impl Request {
         let mut req = Request {
             method:  method.into(),
             uri:     uri.into(),
+            original_uri: None,
+            target_form: TargetForm::Origin,
             version: version.into(),
             headers: Headers::new(),
-            ip: String::new() // TODO: replace it by `None`
+            body: Vec::new(),
+            ip: String::new(), // TODO: replace it by `None`
+            peer_ip: String::new(),
+            scheme: "http".into(),
+            user: None,
+            auth_scheme: None,
+            start_time: time::now(),
+            timings: Timings::new()
         };
-        req.headers.set("host".into(), host.into());
-        req.headers.set("user-agent".into(), user_agent.into());
-        req.headers.set("accept".into(), "*/*".into());
+        req.headers.set("host", host);
+        req.headers.set("user-agent", user_agent);
+        req.headers.set("accept", "*/*");
 
         req
     }
 
     /// Create a `Request` from a raw HTTP request message.
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(message: &str) -> Result<Request, String> {
         let mut lines = message.lines();
 
@@ -62,9 +128,18 @@ impl Request {
         let mut req = Request {
             method:  req_line_fields[0].into(),
             uri:     req_line_fields[1].into(),
+            original_uri: None,
+            target_form: TargetForm::Origin,
             version: req_line_fields[2].into(),
             headers: Headers::new(),
-            ip: String::new() // TODO: replace it by `None`
+            body: Vec::new(),
+            ip: String::new(), // TODO: replace it by `None`
+            peer_ip: String::new(),
+            scheme: "http".into(),
+            user: None,
+            auth_scheme: None,
+            start_time: time::now(),
+            timings: Timings::new()
         };
 
         // Parse the headers
@@ -77,38 +152,158 @@ impl Request {
                     req.headers.set(name, value);
                 }
             }
-            if line == "" {
+            if line.is_empty() {
                 break; // End of headers
             }
         }
-        
+
+        // An absolute URI is reduced to its path and query, and its authority
+        // replaces the Host header.
+        //
+        // (RFC 7230 5.3.2, 5.4)
+        if req.method == "CONNECT" {
+            req.target_form = TargetForm::Authority;
+        } else if req.uri == "*" {
+            req.target_form = TargetForm::Asterisk;
+        } else if let Some((_, authority, path)) = split_absolute_uri(&req.uri) {
+            let (authority, path) = (authority.to_string(), path.to_string());
+            req.original_uri = Some(req.uri.clone());
+            req.target_form = TargetForm::Absolute;
+            req.headers.set("host", &authority);
+            req.uri = path;
+        }
+
         Ok(req)
     }
 
     /// Get the normalized URI of a `Request`.
     pub fn canonicalized_uri(&self) -> String {
-        let mut components = vec![];
-
-        // Rebuild URL to prevent path traversory attack
-        for component in Path::new(&self.uri).components() {
-            match component {
-                Component::ParentDir => { components.pop(); },
-                Component::Normal(s) => { components.push(s.to_str().unwrap()); },
-                _                    => { }
+        // Rebuild URL to prevent path traversory attack, without the query
+        let path = self.uri.split('?').next().unwrap();
+        normalize_path(path)
+    }
+
+    /// Get the normalized path of a `Request` once percent-decoded, like
+    /// `/private` for `/public/%2e%2e/%70rivate`, as it can be understood by
+    /// the applications receiving the URI. This is the path to match against
+    /// the prefixes restricting the access to resources.
+    ///
+    /// (RFC 3986 2.1)
+    pub fn decoded_path(&self) -> String {
+        let path = self.uri.split('?').next().unwrap();
+        normalize_path(&percent_decode(path))
+    }
+
+    /// Read the message-body of the request from `reader`, delimited by
+    /// its `Content-Length` or `Transfer-Encoding` header, or return the
+    /// status code of the error response if it is invalid or larger than
+    /// `max_size` bytes.
+    ///
+    /// A chunked body is given a `Content-Length` header once decoded.
+    pub fn read_body<R: BufRead>(&mut self, reader: R, max_size: usize) -> Result<(), u16> {
+        let mut body = vec![];
+
+        if let Some(transfer_encoding) = self.headers.get("transfer-encoding").cloned() {
+            // The chunked transfer coding is the only one implemented
+            if transfer_encoding.trim().to_lowercase() != "chunked" {
+                return Err(501);
+            }
+            let limit = max_size as u64 + 1;
+            if ChunkedReader::new(reader).take(limit).read_to_end(&mut body).is_err() {
+                return Err(400);
+            }
+            if body.len() > max_size {
+                return Err(413);
+            }
+            self.headers.remove("transfer-encoding");
+            self.headers.set("content-length", &body.len().to_string());
+        } else if let Some(content_length) = self.headers.get("content-length") {
+            let length = match content_length.trim().parse::<u64>() {
+                Ok(length) => length,
+                Err(_)     => return Err(400)
+            };
+            if length > max_size as u64 {
+                return Err(413);
+            }
+            if reader.take(length).read_to_end(&mut body).is_err() || body.len() as u64 != length {
+                return Err(400);
             }
         }
 
-        let mut path = PathBuf::from("/");
-        for component in components {
-            path.push(component);
-        }
-        path.to_str().unwrap().to_string()
+        self.body = body;
+        Ok(())
     }
 
     /// Send the request to the server through a `TcpStream`.
-    pub fn send(&mut self, mut stream: &TcpStream) {
-        let _ = stream.write(&self.to_string().into_bytes());
+    pub fn send(&mut self, mut stream: &TcpStream) -> io::Result<()> {
+        stream.write_all(&self.to_string().into_bytes())?;
+        stream.write_all(&self.body)
+    }
+}
+
+/// Remove the `.` and `..` segments and the empty segments of `path`.
+fn normalize_path(path: &str) -> String {
+    let mut components = vec![];
+    for component in Path::new(path).components() {
+        match component {
+            Component::ParentDir => { components.pop(); },
+            Component::Normal(s) => { components.push(s.to_str().unwrap()); },
+            _                    => { }
+        }
+    }
+
+    let mut path = PathBuf::from("/");
+    for component in components {
+        path.push(component);
+    }
+    path.to_str().unwrap().to_string()
+}
+
+/// Decode the percent-encoded octets of `s`, replacing the invalid UTF-8
+/// sequences.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| str::from_utf8(hex).ok());
+        match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(byte) if bytes[i] == b'%' => {
+                decoded.push(byte);
+                i += 3;
+            },
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
     }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Split an absolute URI, like `http://example.com:8080/index.html?page=2`,
+/// into its scheme, its authority without user information, and its path
+/// and query, or return `None` if it is not an absolute URI.
+///
+/// (RFC 3986 3)
+pub fn split_absolute_uri(uri: &str) -> Option<(&str, &str, String)> {
+    let (scheme, rest) = uri.split_once("://")?;
+    let mut chars = scheme.chars();
+    if !chars.next()?.is_ascii_alphabetic() {
+        return None;
+    }
+    if !chars.all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.') {
+        return None;
+    }
+
+    let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let authority = &rest[..end];
+    let authority = authority.rsplit('@').next().unwrap();
+    let path = rest[end..].split('#').next().unwrap();
+    let path = if path.starts_with('/') { path.to_string() } else { format!("/{}", path) };
+
+    Some((scheme, authority, path))
 }
 
 impl fmt::Display for Request {
@@ -148,6 +343,60 @@ mod tests {
         assert!(req.to_string().starts_with("GET / HTTP/1.1\n"));
     }
 
+    #[test]
+    fn test_from_str() {
+        let req = Request::from_str("GET /index.html HTTP/1.1\nHost: example.com").unwrap();
+        assert_eq!(req.target_form, TargetForm::Origin);
+        assert_eq!(req.uri, "/index.html");
+
+        let req = Request::from_str("GET http://user@example.org:8080?page=2 HTTP/1.1\nHost: example.com").unwrap();
+        assert_eq!(req.target_form, TargetForm::Absolute);
+        assert_eq!(req.uri, "/?page=2");
+        assert_eq!(req.original_uri, Some("http://user@example.org:8080?page=2".into()));
+        assert_eq!(req.headers.get("host"), Some(&"example.org:8080".into()));
+
+        let req = Request::from_str("CONNECT example.org:443 HTTP/1.1\nHost: example.org:443").unwrap();
+        assert_eq!(req.target_form, TargetForm::Authority);
+        assert_eq!(req.uri, "example.org:443");
+
+        let req = Request::from_str("OPTIONS * HTTP/1.1\nHost: example.com").unwrap();
+        assert_eq!(req.target_form, TargetForm::Asterisk);
+    }
+
+    #[test]
+    fn test_split_absolute_uri() {
+        assert_eq!(split_absolute_uri("https://example.com/a?b#c"), Some(("https", "example.com", "/a?b".into())));
+        assert_eq!(split_absolute_uri("http://[::1]:8080"), Some(("http", "[::1]:8080", "/".into())));
+        assert_eq!(split_absolute_uri("/a?b=http://example.com"), None);
+        assert_eq!(split_absolute_uri("1http://example.com"), None);
+    }
+
+    #[test]
+    fn test_read_body() {
+        let mut req = Request::new("POST", "example.com", "/");
+        req.headers.set("content-length", "5");
+        assert_eq!(req.read_body("Hello, World!".as_bytes(), 1024), Ok(()));
+        assert_eq!(req.body, b"Hello".to_vec());
+
+        let mut req = Request::new("POST", "example.com", "/");
+        req.headers.set("transfer-encoding", "chunked");
+        assert_eq!(req.read_body("5\r\nHello\r\n0\r\n\r\n".as_bytes(), 1024), Ok(()));
+        assert_eq!(req.body, b"Hello".to_vec());
+        assert_eq!(req.headers.get("content-length"), Some(&"5".into()));
+
+        let mut req = Request::new("POST", "example.com", "/");
+        req.headers.set("content-length", "13");
+        assert_eq!(req.read_body("Hello, World!".as_bytes(), 5), Err(413));
+
+        let mut req = Request::new("POST", "example.com", "/");
+        req.headers.set("content-length", "20");
+        assert_eq!(req.read_body("Hello, World!".as_bytes(), 1024), Err(400));
+
+        let mut req = Request::new("POST", "example.com", "/");
+        req.headers.set("transfer-encoding", "gzip, chunked");
+        assert_eq!(req.read_body("".as_bytes(), 1024), Err(501));
+    }
+
     #[test]
     fn test_canonicalized_uri() {
         let req = Request::new("GET", "example.com", "/../aa");
@@ -161,5 +410,21 @@ mod tests {
         let req = Request::new("GET", "example.com", "/aa/");
         assert_eq!(req.uri, "/aa/");
         assert_eq!(req.canonicalized_uri(), "/aa");
+
+        let req = Request::new("GET", "example.com", "/aa?page=/../bb");
+        assert_eq!(req.canonicalized_uri(), "/aa");
+    }
+
+    #[test]
+    fn test_decoded_path() {
+        let req = Request::new("GET", "example.com", "/%70rivate/x?a=%2e");
+        assert_eq!(req.decoded_path(), "/private/x");
+
+        let req = Request::new("GET", "example.com", "/public/%2e%2e/private");
+        assert_eq!(req.canonicalized_uri(), "/public/%2e%2e/private");
+        assert_eq!(req.decoded_path(), "/private");
+
+        let req = Request::new("GET", "example.com", "/a%2F..%2F..%2Fb/caf%C3%A9%");
+        assert_eq!(req.decoded_path(), "/b/café%");
     }
 }
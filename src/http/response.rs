@@ -1,11 +1,64 @@
 extern crate time;
 
 use std::fmt;
+use std::fs::File;
+use std::io;
 use std::io::prelude::*;
 use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
 
-use http::headers::Headers;
+use http::headers::{self, Headers};
+use http::request::Request;
+use http::sendfile;
 use http::server::Server;
+use http::upgrade::Connection;
+
+/// Reason-Phrases of the status codes set with `Response::set_status`.
+const REASON_PHRASES: [(u16, &str); 30] = [
+    (101, "Switching Protocols"),
+    (200, "Ok"),
+    (204, "No Content"),
+    (206, "Partial Content"),
+    (301, "Moved Permanently"),
+    (302, "Found"),
+    (303, "See Other"),
+    (304, "Not Modified"),
+    (307, "Temporary Redirect"),
+    (308, "Permanent Redirect"),
+    (400, "Bad Request"),
+    (401, "Unauthorized"),
+    (403, "Forbidden"),
+    (404, "Not Found"),
+    (405, "Method Not Allowed"),
+    (406, "Not Acceptable"),
+    (407, "Proxy Authentication Required"),
+    (408, "Request Timeout"),
+    (410, "Gone"),
+    (411, "Length Required"),
+    (413, "Content Too Large"),
+    (414, "URI Too Long"),
+    (416, "Range Not Satisfiable"),
+    (426, "Upgrade Required"),
+    (429, "Too Many Requests"),
+    (500, "Internal Server Error"),
+    (501, "Not Implemented"),
+    (502, "Bad Gateway"),
+    (503, "Service Unavailable"),
+    (504, "Gateway Timeout")
+];
+
+/// Source of a streamed message-body
+enum BodySource {
+    File(File),
+    Reader(Box<dyn Read + Send>)
+}
+
+/// Source of a streamed message-body, shared between the clones
+/// of a response.
+type SharedBodySource = Arc<Mutex<Option<BodySource>>>;
+
+/// Function taking over the connection after the response.
+pub type Upgrade = Box<dyn FnOnce(Connection) + Send>;
 
 /// HTTP response message
 #[derive(Clone)]
@@ -28,61 +81,275 @@ pub struct Response {
     /// and about further access to the resource identified by the Request-URI.
     pub headers: Headers,
 
+    /// Boolean indicating if the response is complete and can be sent.
+    ended: bool,
+
     /// Boolean indicating if the message head (status-line + headers) has
     /// been sent.
     head_sent: bool,
 
+    /// Number of bytes of the message head written to the stream.
+    head_len: usize,
+
     /// The message-body (if any) of an HTTP message is used to carry the
     /// entity-body associated with the request or response.
     body: Vec<u8>,
 
+    /// Source from which the message-body is streamed when it is written,
+    /// instead of being sent from `body`.
+    source: Option<SharedBodySource>,
+
+    /// Number of bytes of the message-body written to the stream.
+    body_sent: usize,
+
+    /// Request modified by a handler, to be given to the following
+    /// handlers instead of the original request.
+    updated_request: Option<Request>,
+
+    /// Request of an internal redirect, to be processed again by every
+    /// handler of the server instead of the original request.
+    redirected_request: Option<Request>,
+
+    /// Function taking over the connection after the response has been
+    /// sent, shared between the clones of a response.
+    upgrade: Option<Arc<Mutex<Option<Upgrade>>>>,
+
+    /// Boolean indicating if the connection has been taken over before
+    /// writing the response, which is then never written.
+    hijacked: bool,
+
     pub server: Server
 }
 
 impl Response {
     /// Create an HTTP message response.
     pub fn new(server: Server) -> Response {
-        let time = time::now();
-        let date = time::strftime("%a, %d %b %y %T %Z", &time).unwrap();
+        let date = headers::http_date(&time::now_utc());
 
         Response {
             status_code: 200,
             status_message: "Ok".into(),
-            date: date, // TODO: set it to None
+            date, // TODO: set it to None
+            ended: false,
             head_sent: false,
+            head_len: 0,
             headers: Headers::new(),
             body: Vec::new(),
-            server: server
+            source: None,
+            body_sent: 0,
+            updated_request: None,
+            redirected_request: None,
+            upgrade: None,
+            hijacked: false,
+            server
         }
     }
 
     /// Write to `stream` the status-line and the headers
     /// of the response message.
     pub fn write_head(&mut self, mut stream: &TcpStream) {
-        // Set headers
-        if !self.headers.contains_key("content-length") {
+        if self.hijacked {
+            self.head_sent = true;
+            return;
+        }
+
+        // Set headers, without a content-length for a streamed body of
+        // unknown length that will be delimited by closing the connection,
+        // or when the connection will be taken over after the head.
+        //
+        // A server MUST NOT send a Content-Length header field in any
+        // response with a status code of 1xx (Informational) or 204 (No
+        // Content).
+        //
+        // A server MUST NOT send Content-Length in [a 304 (Not Modified)]
+        // response unless its field-value equals the decimal number of
+        // octets that would have been sent in the payload body of a 200 (OK)
+        // response to the same request.
+        //
+        // (RFC 7230 3.3.2)
+        let is_upgraded = self.upgrade.is_some();
+        let is_bodiless = self.status_code < 200 || self.status_code == 204 || self.status_code == 304;
+        if !self.headers.contains_key("content-length") && !self.is_streamed() && !is_upgraded && !is_bodiless {
             let content_length = self.body.len().to_string();
             self.headers.set("content-length", &content_length);
         }
         let date = self.date.clone();
         self.headers.set("server", "SimpletonHTTP/0.0.0");
         self.headers.set("date", &date);
-        self.headers.set("connection", "close");
+        if !is_upgraded {
+            self.headers.set("connection", "close");
+        }
 
         // Send head
-        let _ = stream.write(&self.to_string().into_bytes());
+        let head = self.to_string().into_bytes();
+        if stream.write_all(&head).is_ok() {
+            self.head_len = head.len();
+        }
         self.head_sent = true;
     }
 
     /// Write to `stream` the response message.
     /// 
     /// This method will first write the status-line and the headers
-    /// if it has not already been done, then it will write the message body.
+    /// if it has not already been done, then it will write the part of the
+    /// message body that has not already been written.
     pub fn write(&mut self, mut stream: &TcpStream) {
         if !self.head_sent {
             self.write_head(stream);
         }
-        let _ = stream.write(&self.body);
+        if let Some(source) = self.take_source() {
+            let copied = match source {
+                BodySource::File(mut file)     => sendfile::copy(&mut file, stream),
+                BodySource::Reader(mut reader) => io::copy(&mut reader, &mut stream)
+            };
+            if let Ok(n) = copied {
+                self.body_sent += n as usize;
+            }
+            return;
+        }
+        let unsent = self.body.get(self.body_sent..).unwrap_or(&[]);
+        if !unsent.is_empty() && stream.write_all(unsent).is_ok() {
+            self.body_sent = self.body.len();
+        }
+    }
+
+    /// Return `true` if a handler has ended the response.
+    pub fn is_ended(&self) -> bool {
+        self.ended
+    }
+
+    /// Return `true` if the message head has been written to the stream.
+    pub fn is_sent(&self) -> bool {
+        self.head_sent
+    }
+
+    /// Return the number of bytes of the message (head and body) written
+    /// to the stream.
+    pub fn bytes_sent(&self) -> usize {
+        self.head_len + self.body_sent
+    }
+
+    /// Return the number of bytes of the message-body written to the stream.
+    pub fn body_sent(&self) -> usize {
+        self.body_sent
+    }
+
+    /// Return the message-body of the response.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Return `true` if the message-body will be streamed from a file
+    /// or a reader.
+    pub fn is_streamed(&self) -> bool {
+        self.source.is_some()
+    }
+
+    /// Replace the message-body of the response.
+    pub fn set_body(&mut self, body: Vec<u8>) {
+        self.body = body;
+    }
+
+    /// Send the message-body by streaming it from `reader` when
+    /// the response is written, then end the response.
+    ///
+    /// The `content-length` header should be set if the length of the body
+    /// is known, otherwise the end of the body will be indicated by closing
+    /// the connection.
+    pub fn send_reader<R: Read + Send + 'static>(&mut self, reader: R) {
+        let source = BodySource::Reader(Box::new(reader));
+        self.source = Some(Arc::new(Mutex::new(Some(source))));
+        self.end();
+    }
+
+    /// Send the message-body from `file`, starting at its current position,
+    /// when the response is written, then end the response.
+    ///
+    /// The file is copied to the stream by the kernel with `sendfile(2)`
+    /// on Linux, unless the body is transformed in the meantime (for
+    /// example by compression).
+    pub fn send_file(&mut self, file: File) {
+        let source = BodySource::File(file);
+        self.source = Some(Arc::new(Mutex::new(Some(source))));
+        self.end();
+    }
+
+    /// Take the file or the reader of a streamed message-body as a reader.
+    pub fn take_reader(&mut self) -> Option<Box<dyn Read + Send>> {
+        match self.take_source() {
+            Some(BodySource::File(file))     => Some(Box::new(file)),
+            Some(BodySource::Reader(reader)) => Some(reader),
+            None                             => None
+        }
+    }
+
+    fn take_source(&mut self) -> Option<BodySource> {
+        self.source.take().and_then(|source| source.lock().unwrap().take())
+    }
+
+    /// Set the status code of the response, with its standard
+    /// Reason-Phrase.
+    pub fn set_status(&mut self, status_code: u16) {
+        let status_message = match REASON_PHRASES.iter().find(|&&(code, _)| code == status_code) {
+            Some(&(_, message)) => message,
+            None                => ""
+        };
+        self.status_code = status_code;
+        self.status_message = status_message.into();
+    }
+
+    /// Give `req` to the following handlers instead of the request
+    /// received by the current handler, for example after rewriting
+    /// its URI.
+    pub fn update_request(&mut self, req: Request) {
+        self.updated_request = Some(req);
+    }
+
+    /// Take the request modified by the last handler, if any.
+    pub fn take_updated_request(&mut self) -> Option<Request> {
+        self.updated_request.take()
+    }
+
+    /// Process `req` with every handler of the server, from the first one,
+    /// instead of the request received by the current handler, for example
+    /// after a local redirect of a CGI script. The response is discarded.
+    pub fn redirect_request(&mut self, req: Request) {
+        self.redirected_request = Some(req);
+    }
+
+    /// Take the request of an internal redirect of the last handler, if any.
+    pub fn take_redirected_request(&mut self) -> Option<Request> {
+        self.redirected_request.take()
+    }
+
+    /// Take over the connection with `upgrade` once the message head of the
+    /// response has been sent, for example after switching to another
+    /// protocol with a `101` status code.
+    pub fn on_upgrade<F: FnOnce(Connection) + Send + 'static>(&mut self, upgrade: F) {
+        let upgrade: Upgrade = Box::new(upgrade);
+        self.upgrade = Some(Arc::new(Mutex::new(Some(upgrade))));
+    }
+
+    /// Take over the connection with `upgrade` without writing anything,
+    /// then end the response.
+    ///
+    /// The function is called after all the handlers, and it is responsible
+    /// for writing a response to the client.
+    pub fn hijack<F: FnOnce(Connection) + Send + 'static>(&mut self, upgrade: F) {
+        self.on_upgrade(upgrade);
+        self.hijacked = true;
+        self.end();
+    }
+
+    /// Return `true` if the connection has been taken over without writing
+    /// the response.
+    pub fn is_hijacked(&self) -> bool {
+        self.hijacked
+    }
+
+    /// Take the function taking over the connection, if any.
+    pub fn take_upgrade(&mut self) -> Option<Upgrade> {
+        self.upgrade.take().and_then(|upgrade| upgrade.lock().unwrap().take())
     }
 
     pub fn send(&mut self, chunk: &[u8]) {
@@ -92,11 +359,14 @@ impl Response {
         self.end(); // TODO: remove this if it can be called multiple times
     }
 
+    /// Mark the response as complete, it will be sent by the server
+    /// after the current handler and the following handlers should not
+    /// modify it.
     pub fn end(&mut self) {
         // TODO: prevent from calling multiple times?
-        let time = time::now();
-        let date = time::strftime("%a, %d %b %y %T %Z", &time).unwrap();
+        let date = headers::http_date(&time::now_utc());
         self.date = date;
+        self.ended = true;
     }
 }
 
@@ -126,6 +396,8 @@ impl fmt::Display for Response {
 mod tests {
     use super::*;
 
+    use std::net::TcpListener;
+
     use http::server::Server;
 
     #[test]
@@ -143,4 +415,61 @@ mod tests {
 
         assert!(res.to_string().starts_with("HTTP/1.1 200 Ok\n"));
     }
+
+    #[test]
+    fn test_set_status() {
+        let server = Server::new();
+        let mut res = Response::new(server);
+
+        res.set_status(404);
+        assert!(res.to_string().starts_with("HTTP/1.1 404 Not Found\n"));
+        res.set_status(499);
+        assert_eq!(res.status_message, "");
+    }
+
+    #[test]
+    fn test_write_head() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        let mut res = Response::new(Server::new());
+        res.set_status(101);
+        res.headers.set("upgrade", "foo");
+        res.on_upgrade(|_| {});
+        res.end();
+        res.write(&stream);
+        assert!(!res.headers.contains_key("content-length"));
+        assert_eq!(res.headers.get("connection"), None);
+
+        let mut res = Response::new(Server::new());
+        res.hijack(|_| {});
+        res.write(&stream);
+        assert!(res.is_sent());
+        assert_eq!(res.bytes_sent(), 0);
+
+        drop(stream);
+        let mut received = String::new();
+        client.read_to_string(&mut received).unwrap();
+        assert!(received.starts_with("HTTP/1.1 101 Switching Protocols\n"));
+        assert!(received.ends_with("upgrade: foo\n\n"));
+
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let mut res = Response::new(Server::new());
+        res.set_status(304);
+        res.end();
+        res.write(&stream);
+        assert!(!res.headers.contains_key("content-length"));
+    }
+
+    #[test]
+    fn test_end() {
+        let server = Server::new();
+        let mut res = Response::new(server);
+
+        assert!(!res.is_ended());
+        res.send("Hello, World!".as_bytes());
+        assert!(res.is_ended());
+    }
 }
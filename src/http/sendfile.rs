@@ -0,0 +1,90 @@
+use std::fs::File;
+use std::io;
+use std::net::TcpStream;
+#[cfg(target_os = "linux")]
+use libc;
+
+/// Maximum number of bytes transferred by a single call to `sendfile(2)`.
+#[cfg(target_os = "linux")]
+const MAX_CHUNK: usize = 0x7fff_f000;
+
+/// Copy the content of `file`, from its current position, to `stream`
+/// and return the number of bytes copied.
+///
+/// On Linux the content is copied by the kernel with `sendfile(2)`,
+/// without going through userspace, falling back to a buffered copy if
+/// the file does not support it.
+#[cfg(target_os = "linux")]
+pub fn copy(file: &mut File, stream: &TcpStream) -> io::Result<u64> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut copied = 0;
+    loop {
+        let n = unsafe {
+            libc::sendfile(stream.as_raw_fd(), file.as_raw_fd(), ::std::ptr::null_mut(), MAX_CHUNK)
+        };
+        if n < 0 {
+            let err = io::Error::last_os_error();
+            match err.raw_os_error() {
+                Some(libc::EINTR) => continue,
+                Some(libc::EINVAL) | Some(libc::ENOSYS) if copied == 0 => {
+                    return buffered_copy(file, stream);
+                },
+                _ => return Err(err)
+            }
+        }
+        if n == 0 {
+            return Ok(copied);
+        }
+        copied += n as u64;
+    }
+}
+
+/// Copy the content of `file`, from its current position, to `stream`
+/// and return the number of bytes copied.
+#[cfg(not(target_os = "linux"))]
+pub fn copy(file: &mut File, stream: &TcpStream) -> io::Result<u64> {
+    buffered_copy(file, stream)
+}
+
+fn buffered_copy(file: &mut File, mut stream: &TcpStream) -> io::Result<u64> {
+    io::copy(file, &mut stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+    use std::io::prelude::*;
+    use std::net::TcpListener;
+    use std::thread;
+    use time;
+
+    #[test]
+    fn test_copy() {
+        let path = env::temp_dir().join(format!("simpleton-sendfile-{}", time::precise_time_ns()));
+        let content = "Hello, World!\n".repeat(10000);
+        fs::write(&path, &content).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut received = String::new();
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.read_to_string(&mut received).unwrap();
+            received
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        let mut file = File::open(&path).unwrap();
+        let copied = copy(&mut file, &stream).unwrap();
+        drop(stream);
+
+        assert_eq!(copied, content.len() as u64);
+        assert_eq!(client.join().unwrap(), content);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -3,21 +3,147 @@ use std::io::BufReader;
 use std::io::prelude::*;
 use std::net::{TcpListener, TcpStream};
 use std::str;
+use std::sync::Arc;
 use std::thread;
+use time;
 
+use http::compression::{self, Compression};
+use http::cidr::Cidr;
+use http::file_cache::FileCache;
+use http::forwarded;
+use http::glob;
+use http::headers;
+use http::handlers::acl::Acl;
+use http::handlers::basic_auth::BasicAuth;
+use http::handlers::cgi::Cgi;
+use http::handlers::digest_auth::DigestAuth;
+use http::handlers::error_pages;
+use http::handlers::fastcgi::FastCgi;
+use http::handlers::forward_proxy::ForwardProxy;
+use http::handlers::metrics::Metrics;
+use http::handlers::print_log::LogFormat;
+use http::handlers::proxy::Proxy;
+use http::handlers::rewrite::RewriteRule;
+use http::handlers::serve_static::{CacheRule, Symlinks};
+use http::log::LogSink;
 use http::request::Request;
 use http::response::Response;
+use http::timings::Timings;
+use http::upgrade::Connection;
+
+/// Maximum number of internal redirects of a request, preventing loops.
+const MAX_REDIRECTS: usize = 10;
+
+/// A handler takes the request and the response being built, and returns
+/// the response.
+pub type Handler = fn(Request, Response) -> Response;
 
 /// HTTP server
 #[derive(Clone)]
 pub struct Server {
     pub name: String,
-    pub handlers: Vec<fn(Request, Response) -> Response>,
+    pub handlers: Vec<Handler>,
+
+    /// Servers handling the requests for a host name, like `example.test`,
+    /// or a wildcard pattern, like `*.example.test`. The other requests are
+    /// handled by this server, the default host. They are shared rather
+    /// than copied by the clones of the server given to each response.
+    pub virtual_hosts: Arc<Vec<(String, Server)>>,
+
+    /// Maximum size in bytes of the message-body of a request.
+    pub max_body_size: usize,
+
+    /// Handlers called on error responses for an inclusive range of
+    /// status codes.
+    pub error_handlers: Vec<(u16, u16, Handler)>,
+
+    /// Directory containing static error pages like `404.html` or `5xx.html`.
+    pub error_pages_path: Option<String>,
+
+    /// Format of the lines printed by the `print_log` handler.
+    pub log_format: LogFormat,
+
+    /// Destination of the lines printed by the `print_log` handler.
+    pub access_log: LogSink,
+
+    /// Destination of the errors of the server.
+    pub error_log: LogSink,
+
+    /// Compression of the bodies of the responses, if enabled.
+    pub compression: Option<Compression>,
+
+    /// Metrics collected by the server, if enabled, and served by the
+    /// `metrics` handler.
+    pub metrics: Option<Metrics>,
+
+    /// Path of the metrics served by the `metrics` handler.
+    pub metrics_path: String,
+
+    /// Rules rewriting or redirecting requests, the first rule applying
+    /// to a request is used by the `rewrite` handler.
+    pub rewrite_rules: Vec<RewriteRule>,
+
+    /// Proxies whose forwarded client address, scheme and host replace
+    /// those of the requests they send.
+    pub trusted_proxies: Vec<Cidr>,
+
+    /// Access control lists of the paths, used by the `acl` handler.
+    pub acls: Vec<Acl>,
+
+    /// Authentication required by the `basic_auth` handler.
+    pub basic_auth: Option<BasicAuth>,
+
+    /// Authentication required by the `digest_auth` handler.
+    pub digest_auth: Option<DigestAuth>,
+
+    /// Reverse proxy used by the `proxy` handler.
+    pub proxy: Option<Proxy>,
+
+    /// Forward proxy used by the `forward_proxy` handler.
+    pub forward_proxy: Option<ForwardProxy>,
+
+    /// Execution of CGI scripts by the `cgi` handler.
+    pub cgi: Option<Cgi>,
+
+    /// FastCGI application used by the `fastcgi` handler.
+    pub fastcgi: Option<FastCgi>,
+
+    /// Add a `Server-Timing` header giving the duration of the processing
+    /// of the request by each handler.
+    pub server_timing: bool,
 
     // TODO: All of that could be in `serve_static` handler
     pub root_path: String,
     pub allow_trace: bool,
     pub directory_indexes: Vec<String>,
+    pub serve_precompressed: bool,
+    pub cache_rules: Vec<CacheRule>,
+
+    /// Candidates tried in order to resolve the URI to a static file, like
+    /// `["$uri", "$uri.html", "$uri/", "/index.html"]`, where `$uri` is the
+    /// path of the request. The last one is the fallback path, or status
+    /// code like `=404`. The path is served as is when empty.
+    pub try_files: Vec<String>,
+
+    /// Deny access to hidden files and directories, whose name starts with
+    /// a `.`, except those named in `allowed_hidden`.
+    pub deny_hidden: bool,
+
+    /// Names of the hidden files and directories still served when
+    /// `deny_hidden` is set, like `.well-known` (RFC 8615).
+    pub allowed_hidden: Vec<String>,
+
+    /// Glob patterns of the paths from the root of the files to deny
+    /// access to, like `/**/*.bak`.
+    pub deny_patterns: Vec<String>,
+
+    /// Policy for the static files reached through symbolic links.
+    pub symlinks: Symlinks,
+
+    /// Status code of the responses to denied requests, `403` or `404`.
+    pub deny_status: u16,
+
+    pub file_cache: Option<FileCache>,
     pub content_types: HashMap<String, String>
 }
 
@@ -29,34 +155,108 @@ impl Server {
 
         Server {
             handlers: Vec::new(),
+            virtual_hosts: Arc::new(Vec::new()),
+            max_body_size: 1 << 20,
+            error_handlers: Vec::new(),
+            error_pages_path: None,
+            log_format: LogFormat::Common,
+            access_log: LogSink::stdout(),
+            error_log: LogSink::stderr(),
+            compression: None,
+            metrics: None,
+            metrics_path: "/metrics".into(),
+            rewrite_rules: Vec::new(),
+            trusted_proxies: Vec::new(),
+            acls: Vec::new(),
+            basic_auth: None,
+            digest_auth: None,
+            proxy: None,
+            forward_proxy: None,
+            cgi: None,
+            fastcgi: None,
+            server_timing: false,
             root_path: ".".into(),
             name: "Simpleton HTTP Server".into(),
             allow_trace: false,
             directory_indexes: vec!["index.htm".into(), "index.html".into()],
-            content_types: content_types
+            serve_precompressed: false,
+            cache_rules: Vec::new(),
+            try_files: Vec::new(),
+            deny_hidden: false,
+            allowed_hidden: vec![".well-known".into()],
+            deny_patterns: Vec::new(),
+            symlinks: Symlinks::Follow,
+            deny_status: 404,
+            file_cache: None,
+            content_types
         }
     }
 
-    pub fn add_handler(&mut self, f: fn(Request, Response) -> Response) {
+    pub fn add_handler(&mut self, f: Handler) {
         self.handlers.push(f);
     }
 
+    /// Add a server with its own handlers and configuration to handle
+    /// the requests for the hosts matching `pattern`.
+    pub fn add_virtual_host(&mut self, pattern: &str, host: Server) {
+        Arc::make_mut(&mut self.virtual_hosts).push((pattern.to_lowercase(), host));
+    }
+
+    /// Get the virtual host handling requests with the `Host` header `host`,
+    /// an exact name being preferred to a wildcard.
+    pub fn virtual_host(&self, host: &str) -> Option<&Server> {
+        let name = headers::host_name(host);
+
+        self.virtual_hosts.iter()
+            .find(|&(pattern, _)| *pattern == name)
+            .or_else(|| self.virtual_hosts.iter().find(|&(pattern, _)| glob::matches(pattern, &name)))
+            .map(|(_, server)| server)
+    }
+
+    /// Add a handler called to give a body to error responses
+    /// with the status code `code`.
+    pub fn add_error_handler(&mut self, code: u16, f: Handler) {
+        self.error_handlers.push((code, code, f));
+    }
+
+    /// Add a handler called to give a body to error responses
+    /// with a status code between `first` and `last` (included).
+    pub fn add_error_handler_range(&mut self, first: u16, last: u16, f: Handler) {
+        self.error_handlers.push((first, last, f));
+    }
+
+    /// Add a caching policy for the static files matching `pattern`,
+    /// the first rule matching a file is used.
+    pub fn add_cache_rule(&mut self, pattern: &str, cache_control: &str) {
+        self.cache_rules.push(CacheRule::new(pattern, cache_control));
+    }
+
+    /// Write `message` to the error log with the current time.
+    pub fn log_error(&self, message: &str) {
+        let date = time::strftime("%Y-%m-%d %H:%M:%S", &time::now()).unwrap();
+        self.error_log.write_line(&format!("[{}] Error: {}", date, message));
+    }
+
     pub fn listen(self, binding: &str) {
         let listener = match TcpListener::bind(binding) {
-            Err(e)       => { println!("Error: {}", e); return }
+            Err(e)       => { self.log_error(&e.to_string()); return }
             Ok(listener) => listener
         };
 
         for stream in listener.incoming() {
             match stream {
                 Err(e)     => {
-                    println!("Error: {}", e);
+                    self.log_error(&e.to_string());
                     return
                 },
                 Ok(stream) => {
+                    if let Some(ref metrics) = self.metrics {
+                        metrics.open_connection();
+                    }
                     let server = self.clone();
+                    let timings = Timings::new();
                     thread::spawn(move|| {
-                        handle_client(stream, server)
+                        handle_client(stream, server, timings)
                     });
                 }
             }
@@ -67,23 +267,43 @@ impl Server {
 
 }
 
-fn handle_client(stream: TcpStream, server: Server) {
+impl Default for Server {
+    fn default() -> Server {
+        Server::new()
+    }
+}
+
+fn handle_client(stream: TcpStream, server: Server, mut timings: Timings) {
+    process_request(&stream, &server, &mut timings);
+
+    if let Some(ref metrics) = server.metrics {
+        metrics.close_connection();
+    }
+}
+
+fn process_request(stream: &TcpStream, server: &Server, timings: &mut Timings) {
+    let start_time = time::now();
+
     // Read raw request message
     let mut lines = vec![];
-    let mut reader = BufReader::new(&stream);
-    for line in reader.by_ref().lines() {
-        match line {
+    let mut received_bytes = 0;
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
             Err(_) => return,
-            Ok(line) => {
-                if line == "" {
-                    break
-                } else {
-                    lines.push(line)
-                }
-            }
+            Ok(0)  => break,
+            Ok(n)  => received_bytes += n
+        }
+        let line = line.trim_end_matches(&['\r', '\n'][..]);
+        if line.is_empty() {
+            break
+        } else {
+            lines.push(line.to_string())
         }
     }
     let request_message = lines.join("\n");
+    timings.head_parsed = time::precise_time_ns();
 
     // Create Request message
     let mut req = match Request::from_str(&request_message) {
@@ -97,23 +317,119 @@ fn handle_client(stream: TcpStream, server: Server) {
         Ok(peer_addr) => peer_addr.ip()
     };
     req.ip = ip.to_string();
+    req.peer_ip = req.ip.clone();
+    forwarded::resolve(&mut req, &server.trusted_proxies);
+    req.start_time = start_time;
+    req.timings = timings.clone();
 
+    // Select the virtual host of the request
+    let host = req.headers.get("host").and_then(|host| server.virtual_host(host)).unwrap_or(server);
 
     // Create Response message
-    let mut res = Response::new(server.clone());
-
-    // Call all handlers
-    for handler in &server.handlers {
-        match stream.try_clone() {
-            Ok(stream) => {
-                res = handler(req.clone(), res.clone());
-                res.write(&stream);
-            },
-            Err(e) => { panic!("{}", e) }
+    let mut res = Response::new(host.clone());
+
+    // A server MUST respond with a 400 (Bad Request) status code to any
+    // HTTP/1.1 request message that lacks a Host header field.
+    //
+    // (RFC 7230 5.4)
+    if req.version == "HTTP/1.1" && !req.headers.contains_key("host") {
+        res.set_status(400);
+        res.end();
+    }
+
+    // Read the message-body of the request, after telling the client
+    // waiting for it to continue.
+    //
+    // (RFC 7231 5.1.1)
+    if !res.is_ended() {
+        let expect = req.headers.get("expect").map(|expect| expect.to_lowercase());
+        if expect.as_deref() == Some("100-continue") && req.version == "HTTP/1.1" {
+            let mut stream = stream;
+            let _ = stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n");
+        }
+        if let Err(status_code) = req.read_body(&mut reader, host.max_body_size) {
+            res.set_status(status_code);
+            res.end();
+        }
+        received_bytes += req.body.len();
+    }
+
+    // Call all handlers, the response is sent as soon as one of them
+    // has ended it.
+    let mut redirects = 0;
+    let mut i = 0;
+    while i < host.handlers.len() {
+        let handler_start = time::precise_time_ns();
+        res = (host.handlers[i])(req.clone(), res);
+        if let Some(updated_request) = res.take_updated_request() {
+            req = updated_request;
+        }
+        i += 1;
+
+        // The server MUST [process a local redirect] as if the client had
+        // requested it, with a new response.
+        //
+        // (RFC 3875 6.2.2)
+        if let Some(redirected_request) = res.take_redirected_request() {
+            req = redirected_request;
+            res = Response::new(host.clone());
+            redirects += 1;
+            if redirects > MAX_REDIRECTS {
+                host.log_error(&format!("Too many internal redirects for {}", req.uri));
+                res.set_status(500);
+                res.end();
+            } else {
+                i = 0;
+            }
+        }
+        req.timings.handlers.push((handler_start, time::precise_time_ns()));
+
+        if res.is_ended() {
+            res = send_response(stream, host, &mut req, res);
+        }
+    }
+
+    // None of the handlers could handle the request
+    if !res.is_ended() {
+        res.status_code = 404;
+        res.status_message = "Not Found".into();
+        res.end();
+        res = send_response(stream, host, &mut req, res);
+    }
+
+    if let Some(ref metrics) = server.metrics {
+        metrics.record_request(&req, &res, req.timings.total(), received_bytes, res.bytes_sent());
+    }
+
+    // Give the connection to the handler taking it over, with the bytes
+    // already received after the request.
+    if res.is_sent() {
+        if let Some(upgrade) = res.take_upgrade() {
+            let buffered = reader.buffer().to_vec();
+            if let Ok(stream) = stream.try_clone() {
+                upgrade(Connection::new(stream, buffered));
+            }
         }
     }
 }
 
+/// Write the part of the response not already written to `stream`.
+fn send_response(stream: &TcpStream, server: &Server, req: &mut Request, mut res: Response) -> Response {
+    if !res.is_sent() {
+        res = error_pages::handler(req.clone(), res);
+        res = compression::compress(req, res);
+        if server.server_timing {
+            res.headers.set("server-timing", &req.timings.server_timing());
+        }
+    }
+    res.write(stream);
+    if req.timings.write_complete.is_none() && res.is_sent() {
+        req.timings.write_complete = Some(time::precise_time_ns());
+    }
+
+    res
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -121,7 +437,7 @@ mod tests {
     use http::request::Request;
     use http::response::Response;
 
-    #[warn(unused_variables)]
+    #[allow(unused_variables)]
     fn custom_handler(req: Request, mut res: Response) -> Response {
         res.send("Hello, World!".as_bytes());
 
@@ -143,4 +459,134 @@ mod tests {
         server.add_handler(custom_handler);
         assert_eq!(server.handlers.len(), 1);
     }
+
+    #[test]
+    fn test_add_error_handler() {
+        let mut server = Server::new();
+
+        server.add_error_handler(404, custom_handler);
+        server.add_error_handler_range(500, 599, custom_handler);
+        assert_eq!(server.error_handlers.len(), 2);
+        assert_eq!(server.error_handlers[0].0, 404);
+        assert_eq!(server.error_handlers[1].1, 599);
+    }
+
+    #[test]
+    fn test_virtual_host() {
+        let mut server = Server::new();
+        let mut host = Server::new();
+        host.root_path = "/var/www/example".into();
+        server.add_virtual_host("example.test", host);
+        let mut host = Server::new();
+        host.root_path = "/var/www/wildcard".into();
+        server.add_virtual_host("*.example.test", host);
+        let mut host = Server::new();
+        host.root_path = "/var/www/www".into();
+        server.add_virtual_host("www.example.test", host);
+
+        let root_path = |name| server.virtual_host(name).map(|host| host.root_path.clone());
+        assert_eq!(root_path("example.test"), Some("/var/www/example".into()));
+        assert_eq!(root_path("Example.Test:8080"), Some("/var/www/example".into()));
+        assert_eq!(root_path("www.example.test"), Some("/var/www/www".into()));
+        assert_eq!(root_path("blog.example.test"), Some("/var/www/wildcard".into()));
+        assert_eq!(root_path("example.org"), None);
+        assert_eq!(root_path("[::1]:3000"), None);
+
+        let clone = server.clone();
+        assert!(Arc::ptr_eq(&clone.virtual_hosts, &server.virtual_hosts));
+    }
+
+    #[test]
+    fn test_add_cache_rule() {
+        let mut server = Server::new();
+
+        server.add_cache_rule("/assets/**", "max-age=31536000, immutable");
+        server.add_cache_rule("text/html", "no-cache");
+        assert_eq!(server.cache_rules.len(), 2);
+        assert_eq!(server.cache_rules[1].cache_control, "no-cache");
+    }
+
+    fn late_handler(_req: Request, mut res: Response) -> Response {
+        res.headers.set("x-late", "1");
+
+        res
+    }
+
+    fn respond(server: Server, request: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        client.write_all(request.as_bytes()).unwrap();
+        handle_client(stream, server, Timings::new());
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+
+        response
+    }
+
+    #[test]
+    fn test_handle_client() {
+        let request = "GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+
+        // The response is sent once a handler has ended it
+        let mut server = Server::new();
+        server.add_handler(custom_handler);
+        server.add_handler(late_handler);
+        let response = respond(server, request);
+        assert!(response.starts_with("HTTP/1.1 200 Ok\n"));
+        assert!(response.ends_with("\n\nHello, World!"));
+        assert!(!response.contains("x-late"));
+
+        // Or with a 404 status code if none of the handlers ended it
+        let mut server = Server::new();
+        server.add_handler(late_handler);
+        let response = respond(server, request);
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\n"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_local_redirect() {
+        use std::env;
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+
+        use http::handlers::{basic_auth, cgi, serve_static};
+
+        let root = env::temp_dir().join(format!("simpleton-redirect-{}", time::precise_time_ns()));
+        fs::create_dir_all(root.join("cgi-bin")).unwrap();
+        fs::create_dir_all(root.join("private")).unwrap();
+        fs::write(root.join("private/secret.txt"), "secret").unwrap();
+        for (name, location) in &[("secret", "/private/secret.txt"), ("loop", "/cgi-bin/loop")] {
+            let path = root.join("cgi-bin").join(name);
+            fs::write(&path, format!("#!/bin/sh\nprintf 'Location: {}\\n\\n'\n", location)).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let mut server = Server::new();
+        server.root_path = root.to_str().unwrap().into();
+        let users = basic_auth::Htpasswd::parse("alice:{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=").unwrap();
+        let mut auth = basic_auth::BasicAuth::new(users);
+        auth.path_prefixes = vec!["/private".into()];
+        server.basic_auth = Some(auth);
+        server.cgi = Some(Cgi::new(root.join("cgi-bin").to_str().unwrap()));
+        server.add_handler(basic_auth::handler);
+        server.add_handler(cgi::handler);
+        server.add_handler(serve_static::handler);
+
+        // The redirected request is authenticated like any other request
+        let response = respond(server.clone(), "GET /cgi-bin/secret HTTP/1.1\r\nHost: example.com\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized\n"));
+
+        let request = "GET /cgi-bin/secret HTTP/1.1\r\nHost: example.com\r\nAuthorization: Basic YWxpY2U6c2VjcmV0\r\n\r\n";
+        let response = respond(server.clone(), request);
+        assert!(response.starts_with("HTTP/1.1 200 Ok\n"));
+        assert!(response.ends_with("\n\nsecret"));
+
+        let response = respond(server, "GET /cgi-bin/loop HTTP/1.1\r\nHost: example.com\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\n"));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
 }
@@ -0,0 +1,83 @@
+use time;
+
+/// Timestamps of the processing of a request by the server
+///
+/// Timestamps are given in nanoseconds from an arbitrary point in time
+/// by `time::precise_time_ns()`, they can only be compared with each other.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Timings {
+    /// When the connection of the client was accepted.
+    pub accept: u64,
+
+    /// When the head of the request (request-line and headers) was parsed.
+    pub head_parsed: u64,
+
+    /// When each of the handlers called so far started and ended.
+    pub handlers: Vec<(u64, u64)>,
+
+    /// When the response was written to the stream (if it has been).
+    pub write_complete: Option<u64>
+}
+
+impl Timings {
+    /// Create timings for a connection accepted now.
+    pub fn new() -> Timings {
+        let now = time::precise_time_ns();
+
+        Timings {
+            accept: now,
+            head_parsed: now,
+            handlers: Vec::new(),
+            write_complete: None
+        }
+    }
+
+    /// Time in nanoseconds from the accept of the connection to the
+    /// completion of the write of the response, or to now if the response
+    /// has not been written.
+    pub fn total(&self) -> u64 {
+        let end = self.write_complete.unwrap_or_else(time::precise_time_ns);
+        end.saturating_sub(self.accept)
+    }
+
+    /// Value of the `Server-Timing` header, giving in milliseconds the
+    /// duration of the parsing of the request head, of each handler called
+    /// so far (named `handler-<n>` in the order of the server), and the
+    /// total elapsed time.
+    pub fn server_timing(&self) -> String {
+        let mut metrics = vec![
+            metric("parse", self.head_parsed - self.accept)
+        ];
+        for (i, &(start, end)) in self.handlers.iter().enumerate() {
+            metrics.push(metric(&format!("handler-{}", i + 1), end - start));
+        }
+        metrics.push(metric("total", self.total()));
+
+        metrics.join(", ")
+    }
+}
+
+fn metric(name: &str, duration: u64) -> String {
+    format!("{};dur={:.3}", name, duration as f64 / 1_000_000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_timing() {
+        let timings = Timings {
+            accept: 1_000_000,
+            head_parsed: 1_250_000,
+            handlers: vec![(1_300_000, 2_300_000), (2_300_000, 2_350_000)],
+            write_complete: Some(3_000_000)
+        };
+
+        assert_eq!(timings.total(), 2_000_000);
+        assert_eq!(
+            timings.server_timing(),
+            "parse;dur=0.250, handler-1;dur=1.000, handler-2;dur=0.050, total;dur=2.000"
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use std::io;
+use std::io::prelude::*;
+use std::io::Cursor;
+use std::net::TcpStream;
+
+use http::request::Request;
+use http::response::Response;
+
+/// Connection taken over by a handler, with the bytes received after
+/// the request and already buffered by the server.
+pub struct Connection {
+    stream: TcpStream,
+    buffered: Cursor<Vec<u8>>
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream, buffered: Vec<u8>) -> Connection {
+        Connection {
+            stream,
+            buffered: Cursor::new(buffered)
+        }
+    }
+
+    /// Return the underlying stream, for example to set timeouts.
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    /// Return the buffered bytes not read yet.
+    pub fn buffered(&self) -> &[u8] {
+        let position = self.buffered.position() as usize;
+        &self.buffered.get_ref()[position..]
+    }
+
+    /// Return the underlying stream and the buffered bytes not read yet,
+    /// which should be handled before reading from the stream.
+    pub fn into_parts(self) -> (TcpStream, Vec<u8>) {
+        let buffered = self.buffered().to_vec();
+        (self.stream, buffered)
+    }
+}
+
+impl Read for Connection {
+    /// Read the buffered bytes, then read from the stream.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.buffered.read(buf)? {
+            0 => self.stream.read(buf),
+            n => Ok(n)
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Return `true` if the comma-separated list of `value` contains `token`,
+/// ignoring case.
+pub fn has_token(value: Option<&String>, token: &str) -> bool {
+    value.is_some_and(|value| {
+        value.split(',').any(|item| item.trim().eq_ignore_ascii_case(token))
+    })
+}
+
+/// Switch the connection to `protocol`, like `foo/2`, if the client has
+/// asked for it in its `Upgrade` header, by ending the response with
+/// a `101` status code and calling `f` with the connection once it has
+/// been sent.
+///
+/// Return `false` and leave the response unchanged if the client has not
+/// asked for the protocol, the request can then be handled as if the
+/// `Upgrade` header was absent.
+///
+/// (RFC 7230 6.7)
+pub fn switch_protocols<F>(req: &Request, res: &mut Response, protocol: &str, f: F) -> bool
+    where F: FnOnce(Connection) + Send + 'static
+{
+    // A sender of Upgrade MUST also send an "Upgrade" connection option
+    // in the Connection header field.
+    if req.version != "HTTP/1.1" || !has_token(req.headers.get("connection"), "upgrade") {
+        return false;
+    }
+    if !has_token(req.headers.get("upgrade"), protocol) {
+        return false;
+    }
+
+    res.set_status(101);
+    res.headers.set("upgrade", protocol);
+    res.headers.set("connection", "Upgrade");
+    res.on_upgrade(f);
+    res.end();
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::TcpListener;
+
+    use http::server::Server;
+
+    #[test]
+    fn test_connection_read() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        client.write_all(b" World!").unwrap();
+        drop(client);
+
+        let mut connection = Connection::new(stream, b"Hello,".to_vec());
+        let mut buf = [0; 3];
+        connection.read_exact(&mut buf).unwrap();
+        assert_eq!(connection.buffered(), b"lo,");
+
+        let mut rest = String::new();
+        connection.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "lo, World!");
+        assert_eq!(connection.into_parts().1, b"");
+    }
+
+    #[test]
+    fn test_switch_protocols() {
+        let mut req = Request::new("GET", "example.com", "/");
+        req.headers.set("upgrade", "foo/2, bar");
+        req.headers.set("connection", "Upgrade");
+
+        let mut res = Response::new(Server::new());
+        assert!(!switch_protocols(&req, &mut res, "baz", |_| {}));
+        assert!(!res.is_ended());
+        assert!(res.take_upgrade().is_none());
+
+        assert!(switch_protocols(&req, &mut res, "Foo/2", |_| {}));
+        assert!(res.is_ended());
+        assert_eq!(res.status_code, 101);
+        assert_eq!(res.headers.get("upgrade"), Some(&"Foo/2".into()));
+        assert!(res.take_upgrade().is_some());
+
+        req.headers.set("connection", "keep-alive");
+        let mut res = Response::new(Server::new());
+        assert!(!switch_protocols(&req, &mut res, "bar", |_| {}));
+    }
+}
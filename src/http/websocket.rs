@@ -0,0 +1,514 @@
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::TcpStream;
+use std::str;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use sha1_smol::Sha1;
+
+use http::request::Request;
+use http::response::Response;
+use http::upgrade::{self, Connection};
+
+/// GUID concatenated to the key of the client to compute the key accepting
+/// the connection.
+///
+/// (RFC 6455 1.3)
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Version of the protocol defined by RFC 6455.
+const VERSION: &str = "13";
+
+/// Default maximum size of a message, after reassembling its fragments.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 1 << 20;
+
+// Opcodes of the frames (RFC 6455 5.2)
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT:         u8 = 0x1;
+const OP_BINARY:       u8 = 0x2;
+const OP_CLOSE:        u8 = 0x8;
+const OP_PING:         u8 = 0x9;
+const OP_PONG:         u8 = 0xA;
+
+// Status codes of the close frames (RFC 6455 7.4.1)
+pub const CLOSE_NORMAL:         u16 = 1000;
+pub const CLOSE_GOING_AWAY:     u16 = 1001;
+pub const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+pub const CLOSE_INVALID_DATA:   u16 = 1007;
+pub const CLOSE_TOO_BIG:        u16 = 1009;
+
+/// Message received from or sent to a WebSocket client
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+
+    /// Ping, already answered by a pong when it is received.
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+
+    /// Closing handshake, with an optional status code and reason.
+    Close(Option<(u16, String)>)
+}
+
+/// Frame of a message
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>
+}
+
+/// Server side of a WebSocket connection
+pub struct WebSocket {
+    reader: BufReader<Connection>,
+    stream: TcpStream,
+
+    /// Maximum size of a message, larger messages are rejected by closing
+    /// the connection with the status code `1009`.
+    pub max_message_size: usize,
+
+    /// Opcode and payload of the fragments of a message received so far.
+    fragments: Option<(u8, Vec<u8>)>,
+
+    /// Boolean indicating if a close frame has been sent.
+    closed: bool
+}
+
+impl WebSocket {
+    /// Create the server side of a WebSocket connection after the opening
+    /// handshake.
+    pub fn new(connection: Connection) -> io::Result<WebSocket> {
+        let stream = connection.get_ref().try_clone()?;
+        let reader = BufReader::new(connection);
+
+        Ok(WebSocket {
+            reader,
+            stream,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            fragments: None,
+            closed: false
+        })
+    }
+
+    /// Return the underlying stream, for example to set timeouts.
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    /// Read the next message sent by the client, reassembling fragmented
+    /// messages and answering pings.
+    ///
+    /// A protocol violation closes the connection with the appropriate status
+    /// code and returns an error with the kind `InvalidData`.
+    pub fn read_message(&mut self) -> io::Result<Message> {
+        loop {
+            let frame = self.read_frame()?;
+            match frame.opcode {
+                OP_PING => {
+                    if !self.closed {
+                        self.write_frame(OP_PONG, &frame.payload)?;
+                    }
+                    return Ok(Message::Ping(frame.payload));
+                },
+                OP_PONG => {
+                    return Ok(Message::Pong(frame.payload));
+                },
+                OP_CLOSE => {
+                    return self.read_close(&frame.payload);
+                },
+                OP_CONTINUATION => {
+                    match self.fragments {
+                        Some((_, ref mut data)) => data.extend_from_slice(&frame.payload),
+                        None => return Err(self.fail(CLOSE_PROTOCOL_ERROR, "Unexpected continuation frame"))
+                    }
+                },
+                _ => {
+                    if self.fragments.is_some() {
+                        return Err(self.fail(CLOSE_PROTOCOL_ERROR, "Expected continuation frame"));
+                    }
+                    self.fragments = Some((frame.opcode, frame.payload));
+                }
+            }
+
+            if frame.fin {
+                let (opcode, data) = self.fragments.take().unwrap();
+                if opcode == OP_BINARY {
+                    return Ok(Message::Binary(data));
+                }
+                return match String::from_utf8(data) {
+                    Ok(text) => Ok(Message::Text(text)),
+                    Err(_)   => Err(self.fail(CLOSE_INVALID_DATA, "Invalid UTF-8 in text message"))
+                };
+            }
+        }
+    }
+
+    /// Send `message` to the client in a single frame.
+    pub fn send(&mut self, message: Message) -> io::Result<()> {
+        match message {
+            Message::Text(text)    => self.write_frame(OP_TEXT, text.as_bytes()),
+            Message::Binary(data)  => self.write_frame(OP_BINARY, &data),
+            Message::Ping(data)    => self.write_frame(OP_PING, &data),
+            Message::Pong(data)    => self.write_frame(OP_PONG, &data),
+            Message::Close(None)   => self.write_frame(OP_CLOSE, &[]),
+            Message::Close(Some((code, reason))) => self.close(code, &reason)
+        }
+    }
+
+    pub fn send_text(&mut self, text: &str) -> io::Result<()> {
+        self.write_frame(OP_TEXT, text.as_bytes())
+    }
+
+    pub fn send_binary(&mut self, data: &[u8]) -> io::Result<()> {
+        self.write_frame(OP_BINARY, data)
+    }
+
+    pub fn ping(&mut self, data: &[u8]) -> io::Result<()> {
+        self.write_frame(OP_PING, data)
+    }
+
+    /// Start the closing handshake with a status code and a reason, the
+    /// client should answer with a close message.
+    pub fn close(&mut self, code: u16, reason: &str) -> io::Result<()> {
+        let mut payload = code.to_be_bytes().to_vec();
+        payload.extend_from_slice(reason.as_bytes());
+        self.write_frame(OP_CLOSE, &payload)
+    }
+
+    /// Read a frame, checking that it is valid and masked.
+    ///
+    /// (RFC 6455 5.2)
+    fn read_frame(&mut self) -> io::Result<Frame> {
+        let mut head = [0; 2];
+        self.reader.read_exact(&mut head)?;
+        let fin = head[0] & 0x80 != 0;
+        let opcode = head[0] & 0x0F;
+        let masked = head[1] & 0x80 != 0;
+
+        // RSV1, RSV2 and RSV3 MUST be 0 unless an extension is negotiated.
+        if head[0] & 0x70 != 0 {
+            return Err(self.fail(CLOSE_PROTOCOL_ERROR, "Unexpected reserved bits"));
+        }
+        let is_control = opcode & 0x8 != 0;
+        match opcode {
+            OP_CONTINUATION | OP_TEXT | OP_BINARY | OP_CLOSE | OP_PING | OP_PONG => {},
+            _ => return Err(self.fail(CLOSE_PROTOCOL_ERROR, "Unknown opcode"))
+        }
+
+        // All frames sent from client to server are masked.
+        //
+        // (RFC 6455 5.1)
+        if !masked {
+            return Err(self.fail(CLOSE_PROTOCOL_ERROR, "Unmasked frame"));
+        }
+
+        let len = match head[1] & 0x7F {
+            126 => {
+                let mut buf = [0; 2];
+                self.reader.read_exact(&mut buf)?;
+                u16::from_be_bytes(buf) as u64
+            },
+            127 => {
+                let mut buf = [0; 8];
+                self.reader.read_exact(&mut buf)?;
+                u64::from_be_bytes(buf)
+            },
+            len => len as u64
+        };
+
+        // All control frames MUST have a payload length of 125 bytes or less
+        // and MUST NOT be fragmented.
+        //
+        // (RFC 6455 5.5)
+        if is_control && (len > 125 || !fin) {
+            return Err(self.fail(CLOSE_PROTOCOL_ERROR, "Invalid control frame"));
+        }
+        let buffered = self.fragments.as_ref().map_or(0, |(_, data)| data.len() as u64);
+        if !is_control && buffered + len > self.max_message_size as u64 {
+            return Err(self.fail(CLOSE_TOO_BIG, "Message too big"));
+        }
+
+        let mut mask = [0; 4];
+        self.reader.read_exact(&mut mask)?;
+        let mut payload = vec![0; len as usize];
+        self.reader.read_exact(&mut payload)?;
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+
+        Ok(Frame { fin, opcode, payload })
+    }
+
+    /// Read the payload of a close frame, and answer it with the same
+    /// status code if the connection is not already closing.
+    ///
+    /// (RFC 6455 5.5.1)
+    fn read_close(&mut self, payload: &[u8]) -> io::Result<Message> {
+        let close = match payload.len() {
+            0 => None,
+            1 => return Err(self.fail(CLOSE_PROTOCOL_ERROR, "Invalid close frame")),
+            _ => {
+                let code = u16::from_be_bytes([payload[0], payload[1]]);
+                if !is_valid_close_code(code) {
+                    return Err(self.fail(CLOSE_PROTOCOL_ERROR, "Invalid close status code"));
+                }
+                match str::from_utf8(&payload[2..]) {
+                    Ok(reason) => Some((code, reason.to_string())),
+                    Err(_)     => return Err(self.fail(CLOSE_INVALID_DATA, "Invalid UTF-8 in close reason"))
+                }
+            }
+        };
+
+        if !self.closed {
+            match close {
+                Some((code, _)) => self.close(code, "")?,
+                None            => self.write_frame(OP_CLOSE, &[])?
+            }
+        }
+
+        Ok(Message::Close(close))
+    }
+
+    /// Write an unmasked frame with the FIN bit set.
+    fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        if self.closed {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "WebSocket closed"));
+        }
+        if opcode & 0x8 != 0 && payload.len() > 125 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Control frame payload too long"));
+        }
+        if opcode == OP_CLOSE {
+            self.closed = true;
+        }
+
+        self.stream.write_all(&encode_frame(true, opcode, payload, None))
+    }
+
+    /// Close the connection with `code` after a protocol violation, and
+    /// return the error describing it.
+    fn fail(&mut self, code: u16, message: &str) -> io::Error {
+        if !self.closed {
+            let _ = self.close(code, "");
+        }
+
+        io::Error::new(io::ErrorKind::InvalidData, message)
+    }
+}
+
+/// Return `true` if a close frame may contain the status `code`.
+///
+/// (RFC 6455 7.4)
+fn is_valid_close_code(code: u16) -> bool {
+    matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999)
+}
+
+/// Encode a frame, masked with `mask` if any.
+fn encode_frame(fin: bool, opcode: u8, payload: &[u8], mask: Option<[u8; 4]>) -> Vec<u8> {
+    let mut frame = vec![if fin { 0x80 | opcode } else { opcode }];
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    match payload.len() {
+        len @ 0..=125    => frame.push(mask_bit | len as u8),
+        len @ 126..=0xFFFF => {
+            frame.push(mask_bit | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        },
+        len => {
+            frame.push(mask_bit | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+
+    match mask {
+        Some(mask) => {
+            frame.extend_from_slice(&mask);
+            frame.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+        },
+        None => {
+            frame.extend_from_slice(payload);
+        }
+    }
+
+    frame
+}
+
+/// Compute the `sec-websocket-accept` header of the response to a client
+/// sending `key` in its `sec-websocket-key` header.
+///
+/// (RFC 6455 4.2.2)
+pub fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(GUID.as_bytes());
+
+    BASE64.encode(sha1.digest().bytes())
+}
+
+/// Validate the opening handshake of a WebSocket client and end the
+/// response, then call `f` with the connection once the response has been
+/// sent if the handshake is valid.
+///
+/// The response has the status code `101` when the handshake is accepted,
+/// `426` when the client does not request a supported version of the protocol,
+/// or `400` when the handshake is invalid.
+///
+/// (RFC 6455 4.2)
+pub fn accept<F>(req: &Request, res: &mut Response, f: F)
+    where F: FnOnce(WebSocket) + Send + 'static
+{
+    res.end();
+
+    if !upgrade::has_token(req.headers.get("upgrade"), "websocket") {
+        res.set_status(426);
+        res.headers.set("upgrade", "websocket");
+        return;
+    }
+    if req.headers.get("sec-websocket-version").map(|version| version.trim()) != Some(VERSION) {
+        res.set_status(426);
+        res.headers.set("sec-websocket-version", VERSION);
+        return;
+    }
+    let key = req.headers.get("sec-websocket-key").map_or("", |key| key.trim());
+    let is_valid_key = BASE64.decode(key).map(|key| key.len() == 16).unwrap_or(false);
+    let is_upgrade = upgrade::has_token(req.headers.get("connection"), "upgrade");
+    if req.method != "GET" || req.version != "HTTP/1.1" || !is_upgrade || !is_valid_key {
+        res.set_status(400);
+        return;
+    }
+
+    res.set_status(101);
+    res.headers.set("upgrade", "websocket");
+    res.headers.set("connection", "Upgrade");
+    res.headers.set("sec-websocket-accept", &accept_key(key));
+    res.on_upgrade(move |connection| {
+        if let Ok(websocket) = WebSocket::new(connection) {
+            f(websocket);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::TcpListener;
+
+    use http::server::Server;
+
+    /// Connect a client to the server side of a WebSocket which first reads
+    /// `buffered`.
+    fn connect(buffered: Vec<u8>) -> (TcpStream, WebSocket) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        (client, WebSocket::new(Connection::new(stream, buffered)).unwrap())
+    }
+
+    fn masked(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        encode_frame(fin, opcode, payload, Some([1, 2, 3, 4]))
+    }
+
+    #[test]
+    fn test_accept_key() {
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn test_accept() {
+        let mut req = Request::new("GET", "example.com", "/status");
+        req.headers.set("upgrade", "websocket");
+        req.headers.set("connection", "keep-alive, Upgrade");
+        req.headers.set("sec-websocket-version", "13");
+        req.headers.set("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==");
+
+        let mut res = Response::new(Server::new());
+        accept(&req, &mut res, |_| {});
+        assert!(res.is_ended());
+        assert_eq!(res.status_code, 101);
+        assert_eq!(res.headers.get("sec-websocket-accept"), Some(&"s3pPLMBiTxaQ9kYGzzhZRbK+xOo=".into()));
+        assert!(res.take_upgrade().is_some());
+
+        req.headers.set("sec-websocket-version", "8");
+        let mut res = Response::new(Server::new());
+        accept(&req, &mut res, |_| {});
+        assert_eq!(res.status_code, 426);
+        assert_eq!(res.headers.get("sec-websocket-version"), Some(&"13".into()));
+        assert!(res.take_upgrade().is_none());
+
+        req.headers.set("sec-websocket-version", "13");
+        req.headers.set("sec-websocket-key", "c2hvcnQ=");
+        let mut res = Response::new(Server::new());
+        accept(&req, &mut res, |_| {});
+        assert_eq!(res.status_code, 400);
+    }
+
+    #[test]
+    fn test_read_message() {
+        let (mut client, mut websocket) = connect(masked(false, OP_TEXT, b"Hel"));
+
+        client.write_all(&masked(true, OP_PING, b"ping")).unwrap();
+        client.write_all(&masked(true, OP_CONTINUATION, "lo, 世界".as_bytes())).unwrap();
+        client.write_all(&masked(true, OP_BINARY, &[0, 1, 2])).unwrap();
+        client.write_all(&masked(true, OP_CLOSE, &[0x03, 0xE8, b'B', b'y', b'e'])).unwrap();
+
+        assert_eq!(websocket.read_message().unwrap(), Message::Ping(b"ping".to_vec()));
+        assert_eq!(websocket.read_message().unwrap(), Message::Text("Hello, 世界".into()));
+        assert_eq!(websocket.read_message().unwrap(), Message::Binary(vec![0, 1, 2]));
+        assert_eq!(websocket.read_message().unwrap(), Message::Close(Some((1000, "Bye".into()))));
+        assert!(websocket.send_text("Too late").is_err());
+
+        // The ping is answered by a pong and the close frame by a close frame
+        drop(websocket);
+        let mut received = vec![];
+        client.read_to_end(&mut received).unwrap();
+        let mut expected = encode_frame(true, OP_PONG, b"ping", None);
+        expected.extend(encode_frame(true, OP_CLOSE, &[0x03, 0xE8], None));
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn test_read_message_invalid() {
+        let frames = vec![
+            (encode_frame(true, OP_TEXT, b"Hello", None), CLOSE_PROTOCOL_ERROR),
+            (masked(true, OP_CONTINUATION, b"Hello"), CLOSE_PROTOCOL_ERROR),
+            (masked(false, OP_PING, b"Hello"), CLOSE_PROTOCOL_ERROR),
+            (masked(true, 0x3, b"Hello"), CLOSE_PROTOCOL_ERROR),
+            (masked(true, OP_CLOSE, &[0x03, 0xED]), CLOSE_PROTOCOL_ERROR),
+            (masked(true, OP_TEXT, &[0xFF, 0xFE]), CLOSE_INVALID_DATA),
+            (masked(true, OP_BINARY, &[0; 11]), CLOSE_TOO_BIG)
+        ];
+        for (frame, code) in frames {
+            let (mut client, mut websocket) = connect(frame);
+            websocket.max_message_size = 10;
+
+            let err = websocket.read_message().unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+            drop(websocket);
+            let mut received = vec![];
+            client.read_to_end(&mut received).unwrap();
+            assert_eq!(received, encode_frame(true, OP_CLOSE, &code.to_be_bytes(), None));
+        }
+    }
+
+    #[test]
+    fn test_send() {
+        let (mut client, mut websocket) = connect(vec![]);
+
+        let text = "a".repeat(300);
+        websocket.send_text(&text).unwrap();
+        websocket.send(Message::Binary(vec![1, 2])).unwrap();
+        assert!(websocket.ping(&[0; 126]).is_err());
+        websocket.close(CLOSE_GOING_AWAY, "Restarting").unwrap();
+
+        drop(websocket);
+        let mut received = vec![];
+        client.read_to_end(&mut received).unwrap();
+        assert_eq!(&received[..4], &[0x81, 126, 0x01, 0x2C]);
+        assert_eq!(&received[304..308], &[0x82, 2, 1, 2]);
+        assert_eq!(&received[308..312], &[0x88, 12, 0x03, 0xE9]);
+        assert_eq!(&received[312..], b"Restarting");
+    }
+}
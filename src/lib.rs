@@ -1,3 +1,12 @@
+extern crate base64;
+extern crate bcrypt;
+extern crate flate2;
+extern crate getrandom;
+extern crate libc;
+extern crate md5;
+extern crate regex;
+extern crate sha1_smol;
+extern crate sha2;
 extern crate time;
 
 /// An incomplete implementation of `HTTP/1.1`.